@@ -0,0 +1,167 @@
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tftp::TftpPacket;
+use tftp::packet::TftpPacketRef;
+use tftp::proto::{ClientTransfer, Output, Request, ServerTransfer, Transfer, TransferConfig};
+
+// 统计堆分配次数
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const BLKSIZE: u16 = 65464;
+const BLOCKS: usize = 64;
+
+// 执行一次 f 期间的分配次数
+fn allocations<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    drop(f());
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn report(name: &str, count: usize) {
+    println!("{name}: {count} allocations");
+}
+
+fn parse(c: &mut Criterion) {
+    let datagram = TftpPacket::DATA {
+        block: 1,
+        data: vec![0xa5; usize::from(BLKSIZE)],
+    }
+    .serialize();
+    let mut group = c.benchmark_group("parse DATA");
+    group.throughput(Throughput::Bytes(datagram.len() as u64));
+
+    let owned = || black_box(TftpPacket::deserialize(black_box(&datagram)).unwrap());
+    report("parse DATA/owned", allocations(owned));
+    group.bench_function("owned", |b| b.iter(owned));
+
+    let borrowed = || black_box(TftpPacketRef::deserialize(black_box(&datagram)).unwrap());
+    report("parse DATA/borrowed", allocations(borrowed));
+    group.bench_function("borrowed", |b| b.iter(borrowed));
+    group.finish();
+}
+
+fn serialize(c: &mut Criterion) {
+    let block = vec![0xa5; usize::from(BLKSIZE)];
+    let mut group = c.benchmark_group("serialize DATA");
+    group.throughput(Throughput::Bytes(block.len() as u64));
+
+    // 复制块再序列化为新的 Vec
+    let owned = || {
+        let pkt = TftpPacket::DATA {
+            block: 1,
+            data: black_box(&block).to_vec(),
+        };
+        black_box(pkt.serialize())
+    };
+    report("serialize DATA/owned", allocations(owned));
+    group.bench_function("owned", |b| b.iter(owned));
+
+    let mut buf = vec![0u8; 65536];
+    let mut into = || {
+        let pkt = TftpPacketRef::DATA {
+            block: 1,
+            data: black_box(&block),
+        };
+        black_box(pkt.serialize_into(&mut buf).unwrap())
+    };
+    report("serialize DATA/into", allocations(&mut into));
+    group.bench_function("into", |b| b.iter(&mut into));
+    group.finish();
+}
+
+// 执行输出直到需要等待，发送的报文直接交给对端
+fn step(from: &mut dyn Transfer, to: &mut dyn Transfer, source: &[u8], pos: &mut usize) -> bool {
+    let now = Instant::now();
+    loop {
+        match from.poll(now) {
+            Output::Transmit(datagram) => to.on_datagram(datagram, now),
+            Output::Read(len) => {
+                let end = (*pos + len).min(source.len());
+                from.push_block(&source[*pos..end]);
+                *pos = end;
+            }
+            Output::Write(data) => {
+                black_box(data);
+            }
+            Output::Commit => (),
+            Output::Wait(_) => return false,
+            Output::Done => return true,
+            Output::Failed(msg) => panic!("{msg}"),
+        }
+    }
+}
+
+// 在内存中完成一次上传，没有丢包
+fn upload(source: &[u8], windowsize: u16) {
+    let config = TransferConfig::default();
+    let now = Instant::now();
+    let mut client = ClientTransfer::put(
+        "bench.bin",
+        BLKSIZE,
+        windowsize,
+        Some(source.len() as u64),
+        config.clone(),
+    );
+    let Output::Transmit(wrq) = client.poll(now) else {
+        panic!("expect WRQ");
+    };
+    let request = Request::parse(wrq).unwrap();
+    let mut server = ServerTransfer::write(request.options, config);
+    let mut pos = 0;
+    let (mut client_done, mut server_done) = (false, false);
+    while !(client_done && server_done) {
+        if !server_done {
+            server_done = step(&mut server, &mut client, &[], &mut 0);
+        }
+        if !client_done {
+            client_done = step(&mut client, &mut server, source, &mut pos);
+        }
+        // 服务端收完最后一块后等待对端可能的重传
+        if client_done && !server_done {
+            server.on_timeout();
+        }
+    }
+}
+
+fn transfer(c: &mut Criterion) {
+    let source = vec![0xa5; usize::from(BLKSIZE) * BLOCKS + 1];
+    let mut group = c.benchmark_group("upload");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    for windowsize in [1, 8] {
+        let count = allocations(|| upload(&source, windowsize));
+        report(&format!("upload/{windowsize}: {BLOCKS} blocks"), count);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(windowsize),
+            &windowsize,
+            |b, &windowsize| b.iter(|| upload(&source, windowsize)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, serialize, transfer);
+criterion_main!(benches);
@@ -0,0 +1,97 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::time::{Duration, Instant};
+use tftp::proto::{ClientTransfer, Output, Request, ServerTransfer, Transfer, TransferConfig};
+
+// 读取的文件不超过该长度，限制每个事件产生的输出
+const MAX_FILE: usize = 4096;
+const MAX_STEPS: usize = 100_000;
+// 输入的前 7 字节为参数，之后是以 0xff 分隔的报文，空报文表示超时
+const HEADER: usize = 7;
+const SEPARATOR: u8 = 0xff;
+
+// 模拟读取的文件，读到不足一块时即为末尾
+struct Source {
+    len: usize,
+    pos: usize,
+    eof: bool,
+}
+
+// 执行输出直到需要等待，检查不会越界读取或无限输出
+fn run(transfer: &mut dyn Transfer, source: &mut Source, now: Instant) -> bool {
+    for _ in 0..MAX_STEPS {
+        match transfer.poll(now) {
+            Output::Transmit(_) | Output::Write(_) | Output::Commit => (),
+            Output::Read(len) => {
+                assert!(len > 0, "empty read");
+                assert!(!source.eof, "read past end of file");
+                let end = (source.pos + len).min(source.len);
+                source.eof = end - source.pos < len;
+                transfer.push_block(&vec![0; end - source.pos]);
+                source.pos = end;
+            }
+            Output::Wait(_) => return true,
+            Output::Done | Output::Failed(_) => return false,
+        }
+    }
+    panic!("no wait after {MAX_STEPS} outputs");
+}
+
+fn drive(transfer: &mut dyn Transfer, len: usize, events: &[&[u8]]) {
+    let mut source = Source {
+        len,
+        pos: 0,
+        eof: false,
+    };
+    let mut now = Instant::now();
+    for event in events {
+        if !run(transfer, &mut source, now) {
+            return;
+        }
+        now += Duration::from_millis(10);
+        if event.is_empty() {
+            transfer.on_timeout();
+        } else {
+            transfer.on_datagram(event, now);
+        }
+    }
+    run(transfer, &mut source, now);
+}
+
+// 协商和传输过程中收到任意报文都不能 panic 或失控。
+// 第一个报文同时作为服务端收到的请求，其余报文依次交给服务端和客户端
+fuzz_target!(|data: &[u8]| {
+    if data.len() < HEADER {
+        return;
+    }
+    let (header, body) = data.split_at(HEADER);
+    let flags = header[0];
+    let file = usize::from(u16::from_be_bytes([header[1], header[2]])) % MAX_FILE;
+    let blksize = u16::from_be_bytes([header[3], header[4]]);
+    let windowsize = u16::from_be_bytes([header[5], header[6]]);
+    let config = TransferConfig {
+        gbn: flags & 1 != 0,
+        selective: flags & 2 != 0,
+        ..Default::default()
+    };
+    let events: Vec<&[u8]> = body.split(|&b| b == SEPARATOR).collect();
+
+    if let Some(request) = Request::parse(events[0]) {
+        let mut server = if request.write {
+            ServerTransfer::write(request.options, config.clone())
+        } else {
+            ServerTransfer::read(request.options, Some(file as u64), config.clone())
+        };
+        drive(&mut server, file, &events[1..]);
+    }
+
+    let mut client = if flags & 4 != 0 {
+        ClientTransfer::get("fuzz", blksize, windowsize, config)
+    } else {
+        // 大小未知的上传不携带 tsize
+        let filesize = (flags & 8 == 0).then_some(file as u64);
+        ClientTransfer::put("fuzz", blksize, windowsize, filesize, config)
+    };
+    drive(&mut client, file, &events);
+});
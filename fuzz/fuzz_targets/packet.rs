@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tftp::TftpPacket;
+
+// 任意输入都不能让解析 panic，解析成功的报文序列化后应还原为同一报文
+fuzz_target!(|data: &[u8]| {
+    if let Ok(pkt) = TftpPacket::deserialize(data) {
+        let bytes = pkt.serialize();
+        assert_eq!(TftpPacket::deserialize(&bytes).unwrap(), pkt);
+    }
+});
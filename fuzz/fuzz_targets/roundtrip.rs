@@ -0,0 +1,53 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use tftp::{TftpOptions, TftpPacket};
+
+#[derive(Arbitrary, Debug)]
+enum Packet {
+    Rrq(String, String, Vec<(String, String)>),
+    Wrq(String, String, Vec<(String, String)>),
+    Data(u16, Vec<u8>),
+    Ack(u16),
+    Error(u16, String),
+    Oack(Vec<(String, String)>),
+}
+
+// 报文中的字符串以 \0 结尾，本身不能包含 \0
+fn cstr(s: String) -> String {
+    s.replace('\0', "")
+}
+
+// 名称不区分大小写，重复的选项只保留一个
+fn options(pairs: Vec<(String, String)>) -> TftpOptions {
+    pairs
+        .into_iter()
+        .map(|(k, v)| (cstr(k), cstr(v)))
+        .collect()
+}
+
+// deserialize(serialize(p)) == p
+fuzz_target!(|packet: Packet| {
+    let pkt = match packet {
+        Packet::Rrq(filename, mode, opts) => TftpPacket::RRQ {
+            filename: cstr(filename),
+            mode: cstr(mode),
+            options: options(opts),
+        },
+        Packet::Wrq(filename, mode, opts) => TftpPacket::WRQ {
+            filename: cstr(filename),
+            mode: cstr(mode),
+            options: options(opts),
+        },
+        Packet::Data(block, data) => TftpPacket::DATA { block, data },
+        Packet::Ack(block) => TftpPacket::ACK(block),
+        Packet::Error(code, msg) => TftpPacket::ERROR {
+            code,
+            msg: cstr(msg),
+        },
+        Packet::Oack(opts) => TftpPacket::OACK(options(opts)),
+    };
+    let bytes = pkt.serialize();
+    assert_eq!(TftpPacket::deserialize(&bytes).unwrap(), pkt);
+});
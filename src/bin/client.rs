@@ -1,8 +1,14 @@
 use anstyle::AnsiColor;
 use clap::builder::styling::Styles;
 use clap::{Parser, Subcommand};
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 
-use tftp::{SessionConfig, TftpClient};
+use tftp::{
+    Cancelled, Congestion, PortRange, Progress, SessionConfig, TftpClient, TransferHandle,
+    parse_rate,
+};
 
 const STYLES: Styles = Styles::styled()
     .header(AnsiColor::Green.on_default())
@@ -10,6 +16,11 @@ const STYLES: Styles = Styles::styled()
     .literal(AnsiColor::Cyan.on_default())
     .placeholder(AnsiColor::Red.on_default());
 
+// 终端上进度条的刷新间隔，非终端输出时打印进度行的间隔
+const BAR_INTERVAL: Duration = Duration::from_millis(200);
+const LINE_INTERVAL: Duration = Duration::from_secs(5);
+const BAR_WIDTH: usize = 30;
+
 #[derive(Parser, Debug)]
 #[command(about = "A high-performance asynchronous TFTP client")]
 #[command(styles = STYLES)]
@@ -30,6 +41,18 @@ pub struct Cli {
     #[arg(short, long, default_value_t = 3)]
     pub retry: u8,
 
+    /// Adapt timeout to measured RTT, starting from --timeout
+    #[arg(long)]
+    pub adaptive_timeout: bool,
+
+    /// Lower bound (ms) of the adaptive timeout
+    #[arg(long, default_value_t = 20)]
+    pub min_timeout: u64,
+
+    /// Upper bound (ms) of the adaptive timeout
+    #[arg(long, default_value_t = 10000)]
+    pub max_timeout: u64,
+
     /// Block size
     #[arg(short, long, default_value_t = 512)]
     pub blksize: u16,
@@ -38,6 +61,26 @@ pub struct Cli {
     #[arg(short, long, default_value_t = 1)]
     pub windowsize: u16,
 
+    /// Enable selective repeat when the peer supports it
+    #[arg(short, long)]
+    pub selective: bool,
+
+    /// Congestion control for the send window (fixed, aimd)
+    #[arg(long, default_value_t = Congestion::Fixed)]
+    pub congestion: Congestion,
+
+    /// Upload rate limit (bytes/s, K/M/G suffix allowed)
+    #[arg(long, value_parser = parse_rate)]
+    pub rate: Option<u64>,
+
+    /// Local port range, e.g. 50000:50100 or 50000-50100
+    #[arg(long, value_name = "START:END")]
+    pub port_range: Option<PortRange>,
+
+    /// Keep the partially downloaded file when interrupted
+    #[arg(long)]
+    pub keep_partial: bool,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -65,16 +108,120 @@ async fn main() {
         timeout: args.timeout,
         retry: args.retry,
         gbn: false,
+        rate_limit: args.rate,
+        port_range: args.port_range,
+        adaptive_timeout: args.adaptive_timeout,
+        min_timeout: args.min_timeout,
+        max_timeout: args.max_timeout,
+        selective: args.selective,
+        congestion: args.congestion,
+        window_budget: SessionConfig::default().window_budget,
     };
-    let client = TftpClient::new(config, args.blksize, args.windowsize);
+    let client =
+        TftpClient::new(config, args.blksize, args.windowsize).with_keep_partial(args.keep_partial);
+    // Ctrl-C 时通知服务端并清理未完成的下载
+    let handle = TransferHandle::new();
+    let interrupt = handle.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            interrupt.cancel();
+        }
+    });
+    let tty = std::io::stdout().is_terminal();
+    let reporter = tokio::spawn(report_progress(handle.progress(), tty));
 
     let result = match args.command {
-        Command::Get { filename } => client.get_file(args.addr, filename).await,
-        Command::Put { filename } => client.put_file(args.addr, filename).await,
+        Command::Get { filename } => client.get_file_with(args.addr, filename, &handle).await,
+        Command::Put { filename } => client.put_file_with(args.addr, filename, &handle).await,
     };
+    reporter.abort();
+    if tty {
+        // 清除进度条
+        print!("\r\x1b[2K");
+    }
+
+    match result {
+        Ok(stats) => println!(
+            "{} in {:.1}s ({}/s)",
+            format_bytes(stats.bytes as f64),
+            stats.duration.as_secs_f64(),
+            format_bytes(stats.throughput())
+        ),
+        Err(e) if e.is::<Cancelled>() => {
+            eprintln!("Interrupted");
+            std::process::exit(130);
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+// 终端上原地刷新进度条，否则定期打印一行，直到被终止
+async fn report_progress(progress: watch::Receiver<Progress>, tty: bool) {
+    let start = Instant::now();
+    let mut ticker = tokio::time::interval(if tty { BAR_INTERVAL } else { LINE_INTERVAL });
+    // 第一次 tick 立即返回
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let Progress { bytes, total } = *progress.borrow();
+        let elapsed = start.elapsed().as_secs_f64();
+        let rate = bytes as f64 / elapsed;
+        let line = match total {
+            Some(total) if total > 0 => {
+                let ratio = (bytes as f64 / total as f64).min(1.0);
+                let eta = if rate > 0.0 {
+                    format_eta(total.saturating_sub(bytes) as f64 / rate)
+                } else {
+                    "--:--".to_string()
+                };
+                let done = format!(
+                    "{:3.0}%  {} / {}  {}/s  ETA {eta}",
+                    ratio * 100.0,
+                    format_bytes(bytes as f64),
+                    format_bytes(total as f64),
+                    format_bytes(rate)
+                );
+                if tty {
+                    let filled = (ratio * BAR_WIDTH as f64) as usize;
+                    let bar = "#".repeat(filled) + &"-".repeat(BAR_WIDTH - filled);
+                    format!("[{bar}] {done}")
+                } else {
+                    done
+                }
+            }
+            _ => format!("{}  {}/s", format_bytes(bytes as f64), format_bytes(rate)),
+        };
+        let mut stdout = std::io::stdout().lock();
+        if tty {
+            let _ = write!(stdout, "\r\x1b[2K{line}");
+        } else {
+            let _ = writeln!(stdout, "{line}");
+        }
+        let _ = stdout.flush();
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{value:.0} {}", UNITS[unit]),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
 
-    if let Err(e) = result {
-        eprintln!("Error: {e}");
-        std::process::exit(1);
+fn format_eta(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match secs / 3600 {
+        0 => format!("{:02}:{:02}", secs / 60, secs % 60),
+        hours => format!("{hours}:{:02}:{:02}", secs / 60 % 60, secs % 60),
     }
 }
@@ -1,8 +1,10 @@
 use anstyle::AnsiColor;
 use clap::Parser;
 use clap::builder::styling::Styles;
+use log::info;
+use std::time::Duration;
 
-use tftp::{SessionConfig, TftpServer};
+use tftp::{CommandHooks, Congestion, PortRange, SessionConfig, TftpServer, parse_rate};
 
 const STYLES: Styles = Styles::styled()
     .header(AnsiColor::Green.on_default())
@@ -30,9 +32,69 @@ pub struct Cli {
     #[arg(short, long, default_value_t = 3)]
     pub retry: u8,
 
+    /// Adapt timeout to measured RTT, starting from --timeout
+    #[arg(long)]
+    pub adaptive_timeout: bool,
+
+    /// Lower bound (ms) of the adaptive timeout
+    #[arg(long, default_value_t = 20)]
+    pub min_timeout: u64,
+
+    /// Upper bound (ms) of the adaptive timeout
+    #[arg(long, default_value_t = 10000)]
+    pub max_timeout: u64,
+
+    /// Enable selective repeat when the peer supports it
+    #[arg(short, long)]
+    pub selective: bool,
+
+    /// Congestion control for the send window (fixed, aimd)
+    #[arg(long, default_value_t = Congestion::Fixed)]
+    pub congestion: Congestion,
+
+    /// Max bytes buffered for one transfer window, larger requested windowsize is reduced
+    #[arg(long, default_value_t = 4 * 1024 * 1024)]
+    pub window_budget: u64,
+
     /// Enable GO-Back-N
     #[arg(short, long)]
     pub gbn: bool,
+
+    /// Per-session send rate limit (bytes/s, K/M/G suffix allowed)
+    #[arg(long, value_parser = parse_rate)]
+    pub rate: Option<u64>,
+
+    /// Aggregate send rate limit for all sessions (bytes/s, K/M/G suffix allowed)
+    #[arg(long, value_parser = parse_rate)]
+    pub total_rate: Option<u64>,
+
+    /// Transfer port range for session sockets, e.g. 50000:50100 or 50000-50100
+    #[arg(long, value_name = "START:END")]
+    pub port_range: Option<PortRange>,
+
+    /// Serve all transfers from the listen port (NAT friendly)
+    #[arg(long)]
+    pub single_port: bool,
+
+    /// Drain deadline (s) for active transfers on shutdown
+    #[arg(long, default_value_t = 30)]
+    pub drain: u64,
+
+    /// Command run after an upload completes, with TFTP_* env vars describing the transfer
+    #[arg(long, value_name = "PATH")]
+    pub on_upload_complete: Option<std::path::PathBuf>,
+
+    /// Command run after a download completes, with TFTP_* env vars describing the transfer
+    #[arg(long, value_name = "PATH")]
+    pub on_download_complete: Option<std::path::PathBuf>,
+
+    /// Timeout (s) for completion commands, killed when exceeded
+    #[arg(long, default_value_t = 30)]
+    pub hook_timeout: u64,
+
+    /// Max number of completion commands running at once
+    #[arg(long, default_value_t = 4)]
+    pub hook_jobs: usize,
 }
 
 #[tokio::main]
@@ -43,10 +105,66 @@ async fn main() {
         timeout: args.timeout,
         retry: args.retry,
         gbn: args.gbn,
+        rate_limit: args.rate,
+        port_range: args.port_range,
+        adaptive_timeout: args.adaptive_timeout,
+        min_timeout: args.min_timeout,
+        max_timeout: args.max_timeout,
+        selective: args.selective,
+        congestion: args.congestion,
+        window_budget: args.window_budget,
     };
 
     env_logger::init();
 
-    let server = TftpServer::new(args.addr, config);
-    server.run().await.unwrap();
-}
\ No newline at end of file
+    let mut server = TftpServer::new(args.addr, config)
+        .with_drain_timeout(Duration::from_secs(args.drain))
+        .with_single_port(args.single_port);
+    if let Some(rate) = args.total_rate {
+        server = server.with_rate_limit(rate);
+    }
+
+    let mut hooks = CommandHooks::new()
+        .with_timeout(Duration::from_secs(args.hook_timeout))
+        .with_concurrency(args.hook_jobs);
+    if let Some(program) = args.on_upload_complete {
+        hooks = hooks.with_upload_command(program);
+    }
+    if let Some(program) = args.on_download_complete {
+        hooks = hooks.with_download_command(program);
+    }
+    let hooks = (!hooks.is_empty()).then(|| tokio::spawn(hooks.run(server.subscribe_outcomes())));
+
+    let shutdown = server.shutdown_token();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("shutdown signal received");
+        shutdown.cancel();
+    });
+
+    if let Err(e) = server.run().await {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    // 事件通道随服务端关闭，等待已启动的命令结束
+    drop(server);
+    if let Some(hooks) = hooks {
+        let _ = hooks.await;
+    }
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = sigterm.recv() => (),
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
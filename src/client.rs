@@ -1,13 +1,46 @@
-use crate::session::Session;
 use crate::SessionConfig;
+use crate::port::bind_socket;
+use crate::session::Session;
+use crate::stats::{Progress, TransferStats};
 use log::info;
 use std::net::SocketAddr;
-use tokio::net::UdpSocket;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
 
 pub struct TftpClient {
     config: SessionConfig,
     blksize: u16,
     windowsize: u16,
+    keep_partial: bool,
+}
+
+// 单次传输的取消令牌和进度，同一客户端同时进行的传输各自使用一个
+#[derive(Clone, Debug, Default)]
+pub struct TransferHandle {
+    cancel: CancellationToken,
+    progress: watch::Sender<Progress>,
+}
+
+impl TransferHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_cancel_token(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    // 取消传输，向服务端发送 ERROR，传输返回 Cancelled 错误
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    // 本次传输的进度，传输过程中定期更新
+    pub fn progress(&self) -> watch::Receiver<Progress> {
+        self.progress.subscribe()
+    }
 }
 
 impl TftpClient {
@@ -16,28 +49,147 @@ impl TftpClient {
             config,
             blksize,
             windowsize,
+            keep_partial: false,
         }
     }
 
-    pub async fn get_file(&self, addr: SocketAddr, filename: String) -> anyhow::Result<()> {
+    // 下载被取消时保留已收到的部分，默认删除
+    pub fn with_keep_partial(mut self, keep_partial: bool) -> Self {
+        self.keep_partial = keep_partial;
+        self
+    }
+
+    pub async fn get_file(
+        &self,
+        addr: SocketAddr,
+        filename: String,
+    ) -> anyhow::Result<TransferStats> {
+        self.get_file_with(addr, filename, &TransferHandle::new())
+            .await
+    }
+
+    pub async fn put_file(
+        &self,
+        addr: SocketAddr,
+        filename: String,
+    ) -> anyhow::Result<TransferStats> {
+        self.put_file_with(addr, filename, &TransferHandle::new())
+            .await
+    }
+
+    // 取消时向服务端发送 ERROR，返回 Cancelled 错误
+    pub async fn get_file_cancellable(
+        &self,
+        addr: SocketAddr,
+        filename: String,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<TransferStats> {
+        let handle = TransferHandle::new().with_cancel_token(cancel);
+        self.get_file_with(addr, filename, &handle).await
+    }
+
+    pub async fn put_file_cancellable(
+        &self,
+        addr: SocketAddr,
+        filename: String,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<TransferStats> {
+        let handle = TransferHandle::new().with_cancel_token(cancel);
+        self.put_file_with(addr, filename, &handle).await
+    }
+
+    // 通过 handle 取消传输或观察进度
+    pub async fn get_file_with(
+        &self,
+        addr: SocketAddr,
+        filename: String,
+        handle: &TransferHandle,
+    ) -> anyhow::Result<TransferStats> {
         info!("GET {} from {}", filename, addr);
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        let mut session = Session::new(socket, self.config.clone());
+        let mut session = self.session(addr, handle).await?;
         session
-            .send_rrq(addr, &filename, self.blksize, self.windowsize)
-            .await?;
-        session.recv_file().await?;
-        Ok(())
+            .get(addr, &filename, self.blksize, self.windowsize)
+            .await
     }
 
-    pub async fn put_file(&self, addr: SocketAddr, filename: String) -> anyhow::Result<()> {
+    pub async fn put_file_with(
+        &self,
+        addr: SocketAddr,
+        filename: String,
+        handle: &TransferHandle,
+    ) -> anyhow::Result<TransferStats> {
         info!("PUT {} to {}", filename, addr);
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        let mut session = Session::new(socket, self.config.clone());
+        let mut session = self.session(addr, handle).await?;
         session
-            .send_wrq(addr, &filename, self.blksize, self.windowsize)
-            .await?;
-        session.send_file().await?;
-        Ok(())
+            .put(addr, &filename, self.blksize, self.windowsize)
+            .await
+    }
+
+    // 下载到 writer（如烧写程序的标准输入），不经过本地文件
+    pub async fn get_to_writer(
+        &self,
+        addr: SocketAddr,
+        remote: String,
+        writer: impl AsyncWrite + Send + Unpin,
+    ) -> anyhow::Result<TransferStats> {
+        self.get_to_writer_with(addr, remote, writer, &TransferHandle::new())
+            .await
+    }
+
+    pub async fn get_to_writer_with(
+        &self,
+        addr: SocketAddr,
+        remote: String,
+        writer: impl AsyncWrite + Send + Unpin,
+        handle: &TransferHandle,
+    ) -> anyhow::Result<TransferStats> {
+        info!("GET {} from {}", remote, addr);
+        let mut session = self.session(addr, handle).await?;
+        session
+            .get_to_writer(addr, &remote, writer, self.blksize, self.windowsize)
+            .await
+    }
+
+    // 上传 reader 的内容，size_hint 为已知的大小，通过 tsize 告知服务端
+    pub async fn put_from_reader(
+        &self,
+        addr: SocketAddr,
+        remote: String,
+        reader: impl AsyncRead + Send + Unpin,
+        size_hint: Option<u64>,
+    ) -> anyhow::Result<TransferStats> {
+        self.put_from_reader_with(addr, remote, reader, size_hint, &TransferHandle::new())
+            .await
+    }
+
+    pub async fn put_from_reader_with(
+        &self,
+        addr: SocketAddr,
+        remote: String,
+        reader: impl AsyncRead + Send + Unpin,
+        size_hint: Option<u64>,
+        handle: &TransferHandle,
+    ) -> anyhow::Result<TransferStats> {
+        info!("PUT {} to {}", remote, addr);
+        let mut session = self.session(addr, handle).await?;
+        session
+            .put_from_reader(
+                addr,
+                &remote,
+                reader,
+                size_hint,
+                self.blksize,
+                self.windowsize,
+            )
+            .await
+    }
+
+    async fn session(&self, addr: SocketAddr, handle: &TransferHandle) -> anyhow::Result<Session> {
+        let socket = bind_socket(addr, self.config.port_range).await?;
+        let mut session = Session::new(socket.into(), self.config.clone());
+        session.set_progress(handle.progress.clone());
+        session.set_cancel_token(handle.cancel.clone());
+        session.set_keep_partial(self.keep_partial);
+        Ok(session)
     }
 }
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// 拥塞控制：决定发送端实际使用的窗口大小（块数），不超过协商的 windowsize
+pub trait CongestionControl: Send {
+    // 当前允许在途的块数
+    fn window(&self) -> u16;
+    // 新确认了 acked 个块
+    fn on_ack(&mut self, acked: u16);
+    // 通过重复 ACK 等发现丢包
+    fn on_loss(&mut self);
+    // 等待 ACK 超时
+    fn on_timeout(&mut self);
+}
+
+// 为每个传输创建拥塞控制，参数为协商的 windowsize
+pub type CongestionFactory = Arc<dyn Fn(u16) -> Box<dyn CongestionControl> + Send + Sync>;
+
+// 可选的拥塞控制算法
+#[derive(Clone, Default)]
+pub enum Congestion {
+    // 始终使用协商的窗口（默认，与不做拥塞控制时一致）
+    #[default]
+    Fixed,
+    // 慢启动 + 加性增、乘性减
+    Aimd,
+    // 调用方实现的算法，便于比较不同算法的效果
+    Custom(CongestionFactory),
+}
+
+impl Congestion {
+    pub fn custom(
+        factory: impl Fn(u16) -> Box<dyn CongestionControl> + Send + Sync + 'static,
+    ) -> Self {
+        Congestion::Custom(Arc::new(factory))
+    }
+
+    pub fn build(&self, max: u16) -> Box<dyn CongestionControl> {
+        match self {
+            Congestion::Fixed => Box::new(Fixed::new(max)),
+            Congestion::Aimd => Box::new(Aimd::new(max)),
+            Congestion::Custom(factory) => factory(max),
+        }
+    }
+}
+
+impl fmt::Debug for Congestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Congestion::Fixed => f.write_str("Fixed"),
+            Congestion::Aimd => f.write_str("Aimd"),
+            Congestion::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+impl FromStr for Congestion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fixed" => Ok(Congestion::Fixed),
+            "aimd" => Ok(Congestion::Aimd),
+            _ => Err(format!(
+                "unknown congestion control: {s}, expect fixed or aimd"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Congestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Congestion::Fixed => write!(f, "fixed"),
+            Congestion::Aimd => write!(f, "aimd"),
+            Congestion::Custom(_) => write!(f, "custom"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Fixed {
+    max: u16,
+}
+
+impl Fixed {
+    pub fn new(max: u16) -> Self {
+        Self { max }
+    }
+}
+
+impl CongestionControl for Fixed {
+    fn window(&self) -> u16 {
+        self.max
+    }
+
+    fn on_ack(&mut self, _acked: u16) {}
+
+    fn on_loss(&mut self) {}
+
+    fn on_timeout(&mut self) {}
+}
+
+// 类似 TCP Reno：cwnd 低于 ssthresh 时每确认一块加 1，之后每个窗口加 1；
+// 丢包时减半，超时回到 1 重新慢启动
+#[derive(Debug)]
+pub struct Aimd {
+    max: u16,
+    cwnd: f64,
+    ssthresh: f64,
+}
+
+impl Aimd {
+    pub fn new(max: u16) -> Self {
+        Self {
+            max: max.max(1),
+            cwnd: 1.0,
+            ssthresh: f64::from(max),
+        }
+    }
+}
+
+impl CongestionControl for Aimd {
+    fn window(&self) -> u16 {
+        (self.cwnd as u16).clamp(1, self.max)
+    }
+
+    fn on_ack(&mut self, acked: u16) {
+        for _ in 0..acked {
+            if self.cwnd < self.ssthresh {
+                self.cwnd += 1.0;
+            } else {
+                self.cwnd += 1.0 / self.cwnd;
+            }
+        }
+        self.cwnd = self.cwnd.min(f64::from(self.max));
+    }
+
+    fn on_loss(&mut self) {
+        self.ssthresh = (self.cwnd / 2.0).max(1.0);
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_timeout(&mut self) {
+        self.ssthresh = (self.cwnd / 2.0).max(1.0);
+        self.cwnd = 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_uses_negotiated_window() {
+        let mut cc = Fixed::new(8);
+        cc.on_loss();
+        cc.on_timeout();
+        cc.on_ack(3);
+        assert_eq!(cc.window(), 8);
+    }
+
+    // 慢启动阶段每确认一块窗口加 1，每个窗口翻倍
+    #[test]
+    fn aimd_slow_start_doubles_window() {
+        let mut cc = Aimd::new(64);
+        assert_eq!(cc.window(), 1);
+        for expected in [2, 4, 8, 16, 32, 64] {
+            let window = cc.window();
+            cc.on_ack(window);
+            assert_eq!(cc.window(), expected);
+        }
+    }
+
+    // 丢包后窗口减半，之后每确认一个窗口只加 1
+    #[test]
+    fn aimd_halves_on_loss_then_grows_additively() {
+        let mut cc = Aimd::new(64);
+        cc.on_ack(1 + 2 + 4);
+        assert_eq!(cc.window(), 8);
+        cc.on_loss();
+        assert_eq!(cc.window(), 4);
+        // 确认一个窗口加 1：4 块之后不足 5，8 块之后超过 5
+        cc.on_ack(4);
+        assert_eq!(cc.window(), 4);
+        cc.on_ack(4);
+        assert_eq!(cc.window(), 5);
+        for _ in 0..10 {
+            let window = cc.window();
+            cc.on_ack(window);
+            assert!(cc.window() <= window + 1, "{cc:?}");
+        }
+        // 按取整后的窗口确认，每个窗口增加略少于 1
+        assert!((13..=15).contains(&cc.window()), "{cc:?}");
+
+        // 窗口不会减到 1 以下
+        for _ in 0..10 {
+            cc.on_loss();
+        }
+        assert_eq!(cc.window(), 1);
+    }
+
+    // 超时回到 1 重新慢启动，增长到超时前窗口的一半后改为加性增
+    #[test]
+    fn aimd_resets_on_timeout() {
+        let mut cc = Aimd::new(64);
+        cc.on_ack(1 + 2 + 4 + 8);
+        assert_eq!(cc.window(), 16);
+        cc.on_timeout();
+        assert_eq!(cc.window(), 1);
+        cc.on_ack(1 + 2 + 4);
+        assert_eq!(cc.window(), 8);
+        cc.on_ack(8);
+        assert_eq!(cc.window(), 8);
+    }
+
+    // 窗口不超过协商的 windowsize，减半也从上限开始
+    #[test]
+    fn aimd_is_capped_at_max() {
+        let mut cc = Aimd::new(4);
+        cc.on_ack(100);
+        assert_eq!(cc.window(), 4);
+        cc.on_loss();
+        assert_eq!(cc.window(), 2);
+
+        // windowsize 为 0 时按 1 处理
+        let mut cc = Aimd::new(0);
+        cc.on_ack(10);
+        assert_eq!(cc.window(), 1);
+    }
+
+    #[test]
+    fn custom_factory_builds_controller() {
+        let congestion = Congestion::custom(|max| Box::new(Fixed::new(max / 2)));
+        assert_eq!(congestion.build(16).window(), 8);
+        assert_eq!(congestion.to_string(), "custom");
+    }
+
+    #[test]
+    fn parse_congestion() {
+        assert!(matches!("AIMD".parse(), Ok(Congestion::Aimd)));
+        assert!(matches!("fixed".parse(), Ok(Congestion::Fixed)));
+        assert!("reno".parse::<Congestion>().is_err());
+        assert_eq!(Congestion::Aimd.to_string(), "aimd");
+    }
+}
@@ -0,0 +1,77 @@
+use crate::handler::RequestContext;
+use std::io;
+use tokio::io::AsyncRead;
+
+// 动态生成的下载内容
+pub struct Content {
+    pub(crate) reader: Box<dyn AsyncRead + Send + Unpin>,
+    pub(crate) size: Option<u64>,
+}
+
+impl Content {
+    // 大小未知时不回应 tsize 选项
+    pub fn new(reader: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            size: None,
+        }
+    }
+
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+}
+
+impl From<Vec<u8>> for Content {
+    fn from(bytes: Vec<u8>) -> Self {
+        let size = bytes.len() as u64;
+        Self::new(io::Cursor::new(bytes)).with_size(size)
+    }
+}
+
+impl From<String> for Content {
+    fn from(text: String) -> Self {
+        text.into_bytes().into()
+    }
+}
+
+// RRQ 的内容提供者，如按对端生成 pxelinux.cfg/01-<mac>
+pub trait ContentProvider: Send + Sync {
+    // 返回 None 时由服务目录下的文件处理请求，返回错误时以对应的错误码回应
+    fn provide(&self, ctx: &RequestContext<'_>) -> io::Result<Option<Content>>;
+}
+
+impl<F> ContentProvider for F
+where
+    F: Fn(&RequestContext<'_>) -> io::Result<Option<Content>> + Send + Sync,
+{
+    fn provide(&self, ctx: &RequestContext<'_>) -> io::Result<Option<Content>> {
+        self(ctx)
+    }
+}
+
+// 一次上传的接收者，按顺序收到数据
+pub trait Upload: Send {
+    // 返回错误时中止传输，并以对应的错误码回应对端
+    fn write(&mut self, data: &[u8]) -> io::Result<()>;
+    // 数据已全部收到，在确认最后一块之前调用，返回错误时以对应的错误码拒绝这次上传
+    fn commit(self: Box<Self>) -> io::Result<()>;
+    // 传输失败或被中止，已收到的数据应丢弃
+    fn abort(self: Box<Self>);
+}
+
+// WRQ 的上传接收者，可按文件名、对端地址选择是否接管
+pub trait UploadSink: Send + Sync {
+    // 返回 None 时写入服务目录，返回错误时以对应的错误码拒绝请求
+    fn open(&self, ctx: &RequestContext<'_>) -> io::Result<Option<Box<dyn Upload>>>;
+}
+
+impl<F> UploadSink for F
+where
+    F: Fn(&RequestContext<'_>) -> io::Result<Option<Box<dyn Upload>>> + Send + Sync,
+{
+    fn open(&self, ctx: &RequestContext<'_>) -> io::Result<Option<Box<dyn Upload>>> {
+        self(ctx)
+    }
+}
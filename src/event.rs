@@ -0,0 +1,113 @@
+use crate::options::TftpOptions;
+use crate::stats::TransferStats;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+
+// 服务端一次传输的事件
+#[derive(Clone, Debug)]
+pub struct TransferEvent {
+    pub peer: SocketAddr,
+    // 请求中的文件名和传输模式
+    pub filename: String,
+    pub mode: String,
+    // true 为 WRQ（上传）
+    pub write: bool,
+    // 读写的本地文件，确定之前或由内容提供者、上传接收者处理时为 None
+    pub path: Option<PathBuf>,
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum EventKind {
+    RequestReceived,
+    // OACK 中确认的选项，没有 OACK 时为空
+    Negotiated(TftpOptions),
+    // 传输过程中定期发送，累计的字节数和块数
+    Progress { bytes: u64, blocks: u64 },
+    Completed(TransferStats),
+    // 通知对端的错误码（无法确定时为 0）和原因
+    Failed { code: u16, reason: String },
+}
+
+// 服务端的事件通道：所有事件广播给 subscribe 的订阅者，处理不及时的订阅者会丢失较早的事件；
+// 结束事件另外发给 subscribe_outcomes 的订阅者，不会丢失
+#[derive(Clone)]
+pub(crate) struct EventSender {
+    all: broadcast::Sender<TransferEvent>,
+    outcomes: Arc<Mutex<Vec<mpsc::UnboundedSender<TransferEvent>>>>,
+}
+
+impl EventSender {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            all: broadcast::channel(capacity).0,
+            outcomes: Arc::default(),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<TransferEvent> {
+        self.all.subscribe()
+    }
+
+    // 所有 EventSender 都释放后通道关闭
+    pub(crate) fn subscribe_outcomes(&self) -> mpsc::UnboundedReceiver<TransferEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.outcomes.lock().unwrap().push(tx);
+        rx
+    }
+
+    fn send(&self, event: TransferEvent) {
+        if matches!(
+            event.kind,
+            EventKind::Completed(_) | EventKind::Failed { .. }
+        ) {
+            // 顺便移除已关闭的订阅者
+            let mut outcomes = self.outcomes.lock().unwrap();
+            outcomes.retain(|tx| tx.send(event.clone()).is_ok());
+        }
+        // 没有订阅者时发送失败，忽略即可
+        let _ = self.all.send(event);
+    }
+}
+
+// 向订阅者发送一次传输的事件
+pub(crate) struct Emitter {
+    tx: EventSender,
+    peer: SocketAddr,
+    filename: String,
+    mode: String,
+    write: bool,
+    pub(crate) path: Option<PathBuf>,
+}
+
+impl Emitter {
+    pub(crate) fn new(
+        tx: EventSender,
+        peer: SocketAddr,
+        filename: String,
+        mode: String,
+        write: bool,
+    ) -> Self {
+        Self {
+            tx,
+            peer,
+            filename,
+            mode,
+            write,
+            path: None,
+        }
+    }
+
+    pub(crate) fn emit(&self, kind: EventKind) {
+        self.tx.send(TransferEvent {
+            peer: self.peer,
+            filename: self.filename.clone(),
+            mode: self.mode.clone(),
+            write: self.write,
+            path: self.path.clone(),
+            kind,
+        });
+    }
+}
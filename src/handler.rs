@@ -0,0 +1,85 @@
+use crate::options::TftpOptions;
+use log::info;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// 自定义选项的处理结果
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionAction {
+    // 接受但不在 OACK 中确认
+    Ignore,
+    // 在 OACK 中以给定值确认
+    Accept(String),
+    // 拒绝整个请求，以错误码 8 回应给定原因（RFC 2347）
+    Reject(String),
+}
+
+// 处理函数可见的请求信息
+#[derive(Debug)]
+pub struct RequestContext<'a> {
+    pub peer: SocketAddr,
+    // 可改写以选择实际传输的文件，仍受服务目录限制
+    pub filename: String,
+    pub write: bool,
+    // 请求中的全部选项
+    pub options: &'a TftpOptions,
+}
+
+// 自定义/厂商选项的处理函数，如引导程序上报的 hwaddr、build-id
+pub trait OptionHandler: Send + Sync {
+    fn handle(&self, name: &str, value: &str, ctx: &mut RequestContext<'_>) -> OptionAction;
+}
+
+impl<F> OptionHandler for F
+where
+    F: Fn(&str, &str, &mut RequestContext<'_>) -> OptionAction + Send + Sync,
+{
+    fn handle(&self, name: &str, value: &str, ctx: &mut RequestContext<'_>) -> OptionAction {
+        self(name, value, ctx)
+    }
+}
+
+// 按选项名（不区分大小写）注册的处理函数
+#[derive(Clone, Default)]
+pub(crate) struct OptionHandlers {
+    handlers: Vec<(String, Arc<dyn OptionHandler>)>,
+}
+
+impl OptionHandlers {
+    // 同名的处理函数只保留最后注册的
+    pub(crate) fn register(&mut self, name: &str, handler: Arc<dyn OptionHandler>) {
+        self.handlers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.handlers.push((name.to_string(), handler));
+    }
+
+    fn get(&self, name: &str) -> Option<&dyn OptionHandler> {
+        self.handlers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, handler)| handler.as_ref())
+    }
+
+    // 按请求中的顺序调用处理函数，返回需要在 OACK 中确认的选项，拒绝时返回原因
+    pub(crate) fn apply(&self, ctx: &mut RequestContext<'_>) -> Result<TftpOptions, String> {
+        let mut accepted = TftpOptions::new();
+        if self.handlers.is_empty() {
+            return Ok(accepted);
+        }
+        let options = ctx.options;
+        for (name, value) in options.iter() {
+            let Some(handler) = self.get(name) else {
+                continue;
+            };
+            let action = handler.handle(name, value, ctx);
+            info!("{} option {name}={value}: {action:?}", ctx.peer);
+            match action {
+                OptionAction::Ignore => (),
+                OptionAction::Accept(value) => {
+                    accepted.insert(name, value);
+                }
+                OptionAction::Reject(reason) => return Err(reason),
+            }
+        }
+        Ok(accepted)
+    }
+}
@@ -0,0 +1,137 @@
+use crate::event::{EventKind, TransferEvent};
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::{Semaphore, mpsc};
+use tokio::time::timeout;
+use tokio_util::task::TaskTracker;
+
+const DEF_HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+const DEF_HOOK_CONCURRENCY: usize = 4;
+
+// 传输成功后执行的外部命令，通过环境变量传递传输信息：
+// TFTP_PEER_IP、TFTP_PEER_PORT、TFTP_FILENAME、TFTP_PATH（本地文件的绝对路径，可能为空）、
+// TFTP_SIZE（字节）、TFTP_DURATION（秒）、TFTP_MODE（octet/netascii）
+#[derive(Clone, Debug)]
+pub struct CommandHooks {
+    on_upload: Option<PathBuf>,
+    on_download: Option<PathBuf>,
+    timeout: Duration,
+    concurrency: usize,
+}
+
+impl Default for CommandHooks {
+    fn default() -> Self {
+        Self {
+            on_upload: None,
+            on_download: None,
+            timeout: DEF_HOOK_TIMEOUT,
+            concurrency: DEF_HOOK_CONCURRENCY,
+        }
+    }
+}
+
+impl CommandHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // WRQ 完成后执行
+    pub fn with_upload_command(mut self, program: impl Into<PathBuf>) -> Self {
+        self.on_upload = Some(program.into());
+        self
+    }
+
+    // RRQ 完成后执行
+    pub fn with_download_command(mut self, program: impl Into<PathBuf>) -> Self {
+        self.on_download = Some(program.into());
+        self
+    }
+
+    // 超时未退出的命令会被终止
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // 同时运行的命令数上限，其余的排队等待
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.on_upload.is_none() && self.on_download.is_none()
+    }
+
+    // 处理 TftpServer::subscribe_outcomes 的事件直到通道关闭（服务端及其所有会话结束），
+    // 然后等待已启动的命令退出
+    pub async fn run(self, mut events: mpsc::UnboundedReceiver<TransferEvent>) {
+        let tracker = TaskTracker::new();
+        let permits = Arc::new(Semaphore::new(self.concurrency));
+        while let Some(event) = events.recv().await {
+            let program = if event.write {
+                &self.on_upload
+            } else {
+                &self.on_download
+            };
+            if let (Some(program), EventKind::Completed(_)) = (program, &event.kind) {
+                let command = hook_command(program, &event);
+                let permits = permits.clone();
+                let limit = self.timeout;
+                let name = format!("{} {}", program.display(), event.filename);
+                tracker.spawn(async move {
+                    let _permit = permits.acquire_owned().await;
+                    run_command(command, &name, limit).await;
+                });
+            }
+        }
+        tracker.close();
+        tracker.wait().await;
+    }
+}
+
+fn hook_command(program: &Path, event: &TransferEvent) -> Command {
+    let EventKind::Completed(stats) = &event.kind else {
+        unreachable!()
+    };
+    let path = event.path.as_deref().unwrap_or(Path::new(""));
+    let mut command = Command::new(program);
+    command
+        .env("TFTP_PEER_IP", event.peer.ip().to_string())
+        .env("TFTP_PEER_PORT", event.peer.port().to_string())
+        .env("TFTP_FILENAME", &event.filename)
+        .env("TFTP_PATH", path)
+        .env("TFTP_SIZE", stats.bytes.to_string())
+        .env(
+            "TFTP_DURATION",
+            format!("{:.3}", stats.duration.as_secs_f64()),
+        )
+        .env("TFTP_MODE", &event.mode)
+        .stdin(Stdio::null());
+    command
+}
+
+async fn run_command(mut command: Command, name: &str, limit: Duration) {
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            error!("hook {name} failed to start: {e}");
+            return;
+        }
+    };
+    match timeout(limit, child.wait()).await {
+        Ok(Ok(status)) if status.success() => info!("hook {name} finished"),
+        Ok(Ok(status)) => warn!("hook {name} exited with {status}"),
+        Ok(Err(e)) => error!("hook {name} failed: {e}"),
+        Err(_) => {
+            warn!("hook {name} timed out after {limit:?}, killing");
+            if let Err(e) = child.kill().await {
+                error!("hook {name} failed to kill: {e}");
+            }
+        }
+    }
+}
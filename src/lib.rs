@@ -1,9 +1,50 @@
+#[cfg(feature = "tokio")]
 mod client;
-mod packet;
+mod congestion;
+#[cfg(feature = "tokio")]
+mod content;
+#[cfg(feature = "tokio")]
+mod event;
+#[cfg(feature = "tokio")]
+mod handler;
+#[cfg(feature = "tokio")]
+mod hook;
+mod options;
+pub mod packet;
+#[cfg(feature = "tokio")]
+mod port;
+pub mod proto;
+#[cfg(feature = "tokio")]
+mod ratelimit;
+mod rto;
+mod sender;
+#[cfg(feature = "tokio")]
 mod server;
+#[cfg(feature = "tokio")]
 mod session;
-mod window;
+mod stats;
+#[cfg(feature = "tokio")]
+mod transport;
 
-pub use crate::client::TftpClient;
+#[cfg(feature = "tokio")]
+pub use crate::client::{TftpClient, TransferHandle};
+pub use crate::congestion::{Aimd, Congestion, CongestionControl, CongestionFactory, Fixed};
+#[cfg(feature = "tokio")]
+pub use crate::content::{Content, ContentProvider, Upload, UploadSink};
+#[cfg(feature = "tokio")]
+pub use crate::event::{EventKind, TransferEvent};
+#[cfg(feature = "tokio")]
+pub use crate::handler::{OptionAction, OptionHandler, RequestContext};
+#[cfg(feature = "tokio")]
+pub use crate::hook::CommandHooks;
+pub use crate::options::{TftpOptions, TftpOptionsRef};
+pub use crate::packet::{PacketError, TftpPacket};
+#[cfg(feature = "tokio")]
+pub use crate::port::PortRange;
+#[cfg(feature = "tokio")]
+pub use crate::ratelimit::parse_rate;
+#[cfg(feature = "tokio")]
 pub use crate::server::TftpServer;
-pub use crate::session::SessionConfig;
+#[cfg(feature = "tokio")]
+pub use crate::session::{Cancelled, SessionConfig};
+pub use crate::stats::{Progress, TransferStats};
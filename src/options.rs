@@ -0,0 +1,207 @@
+use crate::packet::{PacketError, split_cstr};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+// 已知选项的名称（RFC 2348, RFC 2349, RFC 7440）
+const BLKSIZE: &str = "blksize";
+const TIMEOUT: &str = "timeout";
+const TSIZE: &str = "tsize";
+const WINDOWSIZE: &str = "windowsize";
+
+// RRQ/WRQ/OACK 中的选项：保持插入顺序，按 RFC 2347 不区分名称大小写
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct TftpOptions {
+    entries: Vec<(String, String)>,
+}
+
+impl TftpOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.position(key).map(|i| self.entries[i].1.as_str())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.position(key).is_some()
+    }
+
+    // 已有同名选项时替换其值并保留原来的位置，返回旧值
+    pub fn insert(&mut self, key: impl Into<String>, value: impl ToString) -> Option<String> {
+        let key = key.into();
+        let value = value.to_string();
+        match self.position(&key) {
+            Some(i) => Some(std::mem::replace(&mut self.entries[i].1, value)),
+            None => {
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    // 添加选项，已有同名选项时返回 DuplicateOption
+    pub fn try_insert(
+        &mut self,
+        key: impl Into<String>,
+        value: impl ToString,
+    ) -> Result<(), PacketError> {
+        let key = key.into();
+        if self.contains_key(&key) {
+            return Err(PacketError::DuplicateOption(key));
+        }
+        self.entries.push((key, value.to_string()));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.position(key).map(|i| self.entries.remove(i).1)
+    }
+
+    // 块大小（RFC 2348）
+    pub fn blksize(&self) -> Result<Option<u16>, PacketError> {
+        self.parse(BLKSIZE)
+    }
+
+    pub fn set_blksize(&mut self, blksize: u16) {
+        self.insert(BLKSIZE, blksize);
+    }
+
+    // 超时秒数（RFC 2349）
+    pub fn timeout(&self) -> Result<Option<u8>, PacketError> {
+        self.parse(TIMEOUT)
+    }
+
+    pub fn set_timeout(&mut self, timeout: u8) {
+        self.insert(TIMEOUT, timeout);
+    }
+
+    // 文件大小（RFC 2349）
+    pub fn tsize(&self) -> Result<Option<u64>, PacketError> {
+        self.parse(TSIZE)
+    }
+
+    pub fn set_tsize(&mut self, tsize: u64) {
+        self.insert(TSIZE, tsize);
+    }
+
+    // 窗口大小（RFC 7440）
+    pub fn windowsize(&self) -> Result<Option<u16>, PacketError> {
+        self.parse(WINDOWSIZE)
+    }
+
+    pub fn set_windowsize(&mut self, windowsize: u16) {
+        self.insert(WINDOWSIZE, windowsize);
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(key))
+    }
+
+    fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>, PacketError> {
+        self.get(key)
+            .map(|value| {
+                value.parse().map_err(|_| PacketError::InvalidOption {
+                    name: key.to_string(),
+                    value: value.to_string(),
+                })
+            })
+            .transpose()
+    }
+}
+
+impl<K: Into<String>, V: ToString> FromIterator<(K, V)> for TftpOptions {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut options = Self::new();
+        for (key, value) in iter {
+            options.insert(key, value);
+        }
+        options
+    }
+}
+
+impl fmt::Debug for TftpOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// 借用报文的选项，直接读取原始字节
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct TftpOptionsRef<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> TftpOptionsRef<'a> {
+    // 检查格式并拒绝重复的选项
+    pub(crate) fn parse(raw: &'a [u8]) -> Result<Self, PacketError> {
+        let mut buf = raw;
+        // 用小写名称查重，避免逐个回扫已解析的选项
+        let mut seen = HashSet::new();
+        while !buf.is_empty() {
+            let (key, rest) = split_cstr(buf)?;
+            let (_, rest) = split_cstr(rest)?;
+            if !seen.insert(key.to_ascii_lowercase()) {
+                return Err(PacketError::DuplicateOption(key.to_string()));
+            }
+            buf = rest;
+        }
+        Ok(Self { raw })
+    }
+
+    // 序列化后的原始字节
+    pub(crate) fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + use<'a> {
+        let mut buf = self.raw;
+        std::iter::from_fn(move || {
+            // 已在 parse 中检查过格式
+            let (key, rest) = split_cstr(buf).ok()?;
+            let (value, rest) = split_cstr(rest).ok()?;
+            buf = rest;
+            Some((key, value))
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    pub fn to_options(&self) -> TftpOptions {
+        TftpOptions {
+            entries: self
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Debug for TftpOptionsRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -1,18 +1,68 @@
-use anyhow::{anyhow, Ok};
-use std::collections::HashMap;
+pub use crate::options::{TftpOptions, TftpOptionsRef};
+use std::fmt;
 use std::str;
 
-#[derive(Debug)]
+const OP_RRQ: u16 = 1;
+const OP_WRQ: u16 = 2;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+// 错误码（RFC 1350, RFC 2347）
+pub const ERR_NOT_DEFINED: u16 = 0;
+pub const ERR_FILE_NOT_FOUND: u16 = 1;
+pub const ERR_ACCESS_VIOLATION: u16 = 2;
+pub const ERR_DISK_FULL: u16 = 3;
+pub const ERR_ILLEGAL_OP: u16 = 4;
+pub const ERR_FILE_EXISTS: u16 = 6;
+pub const ERR_OPTION: u16 = 8;
+
+// 解析报文失败的原因
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PacketError {
+    // 报文长度不足以包含操作码、块号或错误码
+    Truncated,
+    InvalidOpcode(u16),
+    // 字符串缺少结尾的 \0
+    MissingTerminator,
+    InvalidUtf8,
+    // 同一选项出现多次（RFC 2347）
+    DuplicateOption(String),
+    // 选项的值无法解析
+    InvalidOption { name: String, value: String },
+    // serialize_into 的缓冲区放不下报文
+    BufferTooSmall,
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::Truncated => write!(f, "Packet length too short"),
+            PacketError::InvalidOpcode(opcode) => write!(f, "Invalid opcode: {opcode}"),
+            PacketError::MissingTerminator => write!(f, "Missing cstr terminator"),
+            PacketError::InvalidUtf8 => write!(f, "Invalid cstr encoding"),
+            PacketError::DuplicateOption(key) => write!(f, "Duplicate option: {key}"),
+            PacketError::InvalidOption { name, value } => write!(f, "Invalid {name}: {value}"),
+            PacketError::BufferTooSmall => write!(f, "Buffer too small"),
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TftpPacket {
     RRQ {
         filename: String,
         mode: String,
-        options: HashMap<String, String>,
+        options: TftpOptions,
     },
     WRQ {
         filename: String,
         mode: String,
-        options: HashMap<String, String>,
+        options: TftpOptions,
     },
     DATA {
         block: u16,
@@ -23,13 +73,30 @@ pub enum TftpPacket {
         code: u16,
         msg: String,
     },
-    OACK(HashMap<String, String>),
+    OACK(TftpOptions),
 }
 
 impl TftpPacket {
     pub fn serialize(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = vec![0];
+        let mut bytes = Vec::new();
+        self.append_to(&mut bytes);
+        bytes
+    }
 
+    // 序列化到给定缓冲区，返回报文长度
+    pub fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, PacketError> {
+        let mut sink = SliceSink { buf, len: 0 };
+        self.encode(&mut sink)?;
+        Ok(sink.len)
+    }
+
+    // 追加到 buf 末尾，复用 buf 已有的容量
+    pub fn append_to(&self, buf: &mut Vec<u8>) {
+        // 写入 Vec 不会失败
+        let _ = self.encode(buf);
+    }
+
+    fn encode(&self, sink: &mut impl Sink) -> Result<(), PacketError> {
         match self {
             TftpPacket::RRQ {
                 filename,
@@ -41,137 +108,248 @@ impl TftpPacket {
                 mode,
                 options,
             } => {
-                if let TftpPacket::RRQ { .. } = self {
-                    bytes.push(1);
-                } else {
-                    bytes.push(2);
-                }
-                bytes.extend(filename.as_bytes());
-                bytes.push(0);
-                bytes.extend(mode.as_bytes());
-                bytes.push(0);
-                for (key, value) in options {
-                    bytes.extend(key.as_bytes());
-                    bytes.push(0);
-                    bytes.extend(value.as_bytes());
-                    bytes.push(0);
-                }
-            }
-            TftpPacket::DATA { block, data } => {
-                bytes.push(3);
-                bytes.push(block.to_be_bytes()[0]);
-                bytes.push(block.to_be_bytes()[1]);
-                bytes.extend_from_slice(data);
+                let opcode = match self {
+                    TftpPacket::RRQ { .. } => OP_RRQ,
+                    _ => OP_WRQ,
+                };
+                sink.put(&opcode.to_be_bytes())?;
+                put_cstr(sink, filename)?;
+                put_cstr(sink, mode)?;
+                put_options(sink, options)
             }
-            TftpPacket::ACK(block) => {
-                bytes.push(4);
-                bytes.push(block.to_be_bytes()[0]);
-                bytes.push(block.to_be_bytes()[1]);
+            TftpPacket::DATA { block, data } => TftpPacketRef::DATA {
+                block: *block,
+                data,
             }
+            .encode(sink),
+            TftpPacket::ACK(block) => TftpPacketRef::ACK(*block).encode(sink),
             TftpPacket::ERROR { code, msg } => {
-                bytes.push(5);
-                bytes.push(code.to_be_bytes()[0]);
-                bytes.push(code.to_be_bytes()[1]);
-                bytes.extend_from_slice(msg.as_bytes());
-                bytes.push(0);
+                TftpPacketRef::ERROR { code: *code, msg }.encode(sink)
             }
             TftpPacket::OACK(nego_options) => {
-                bytes.push(6);
-                for (key, value) in nego_options {
-                    bytes.extend_from_slice(key.as_bytes());
-                    bytes.push(0);
-                    bytes.extend_from_slice(value.as_bytes());
-                    bytes.push(0);
-                }
+                sink.put(&OP_OACK.to_be_bytes())?;
+                put_options(sink, nego_options)
             }
         }
-        bytes
     }
 
-    pub fn deserialize(buf: &[u8]) -> anyhow::Result<Self> {
-        if buf.len() < 4 {
-            return Err(anyhow!("Packet length too short"));
-        }
+    pub fn deserialize(buf: &[u8]) -> Result<Self, PacketError> {
+        TftpPacketRef::deserialize(buf).map(TftpPacketRef::into_owned)
+    }
+}
 
-        let opcode = u16::from_be_bytes([buf[0], buf[1]]);
+// 借用接收缓冲区的报文，解析时不复制数据
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum TftpPacketRef<'a> {
+    RRQ {
+        filename: &'a str,
+        mode: &'a str,
+        options: TftpOptionsRef<'a>,
+    },
+    WRQ {
+        filename: &'a str,
+        mode: &'a str,
+        options: TftpOptionsRef<'a>,
+    },
+    DATA {
+        block: u16,
+        data: &'a [u8],
+    },
+    ACK(u16),
+    ERROR {
+        code: u16,
+        msg: &'a str,
+    },
+    OACK(TftpOptionsRef<'a>),
+}
+
+impl<'a> TftpPacketRef<'a> {
+    pub fn deserialize(buf: &'a [u8]) -> Result<Self, PacketError> {
+        let (opcode, body) = split_u16(buf)?;
         let pkt = match opcode {
-            1 | 2 => {
-                let filename = read_cstr(&buf[2..])?;
-                let mode = read_cstr(&buf[2 + filename.len() + 1..])?;
-                let options = read_options(&buf[2 + filename.len() + 1 + mode.len() + 1..])?;
-                if opcode == 1 {
-                    TftpPacket::RRQ {
+            OP_RRQ | OP_WRQ => {
+                let (filename, rest) = split_cstr(body)?;
+                let (mode, rest) = split_cstr(rest)?;
+                let options = TftpOptionsRef::parse(rest)?;
+                if opcode == OP_RRQ {
+                    TftpPacketRef::RRQ {
                         filename,
                         mode,
                         options,
                     }
                 } else {
-                    TftpPacket::WRQ {
+                    TftpPacketRef::WRQ {
                         filename,
                         mode,
                         options,
                     }
                 }
             }
-            3 => {
-                let block = u16::from_be_bytes([buf[2], buf[3]]);
-                let data = buf[4..].to_vec();
-
-                TftpPacket::DATA { block, data }
+            OP_DATA => {
+                let (block, data) = split_u16(body)?;
+                TftpPacketRef::DATA { block, data }
             }
-            4 => TftpPacket::ACK(u16::from_be_bytes(buf[2..4].try_into()?)),
-            5 => {
-                let code = u16::from_be_bytes([buf[2], buf[3]]);
-                let msg = read_cstr(&buf[4..])?;
+            OP_ACK => TftpPacketRef::ACK(split_u16(body)?.0),
+            OP_ERROR => {
+                let (code, rest) = split_u16(body)?;
+                let (msg, _) = split_cstr(rest)?;
 
-                TftpPacket::ERROR { code, msg }
+                TftpPacketRef::ERROR { code, msg }
             }
-            6 => TftpPacket::OACK(read_options(&buf[2..])?),
+            OP_OACK => TftpPacketRef::OACK(TftpOptionsRef::parse(body)?),
             _ => {
-                return Err(anyhow!("Invalid opcode: {}", opcode));
+                return Err(PacketError::InvalidOpcode(opcode));
             }
         };
 
         Ok(pkt)
     }
+
+    // 序列化到给定缓冲区，返回报文长度
+    pub fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, PacketError> {
+        let mut sink = SliceSink { buf, len: 0 };
+        self.encode(&mut sink)?;
+        Ok(sink.len)
+    }
+
+    // 追加到 buf 末尾，复用 buf 已有的容量
+    pub fn append_to(&self, buf: &mut Vec<u8>) {
+        let _ = self.encode(buf);
+    }
+
+    fn encode(&self, sink: &mut impl Sink) -> Result<(), PacketError> {
+        match self {
+            TftpPacketRef::RRQ {
+                filename,
+                mode,
+                options,
+            }
+            | TftpPacketRef::WRQ {
+                filename,
+                mode,
+                options,
+            } => {
+                let opcode = match self {
+                    TftpPacketRef::RRQ { .. } => OP_RRQ,
+                    _ => OP_WRQ,
+                };
+                sink.put(&opcode.to_be_bytes())?;
+                put_cstr(sink, filename)?;
+                put_cstr(sink, mode)?;
+                sink.put(options.raw())
+            }
+            TftpPacketRef::DATA { block, data } => {
+                sink.put(&OP_DATA.to_be_bytes())?;
+                sink.put(&block.to_be_bytes())?;
+                sink.put(data)
+            }
+            TftpPacketRef::ACK(block) => {
+                sink.put(&OP_ACK.to_be_bytes())?;
+                sink.put(&block.to_be_bytes())
+            }
+            TftpPacketRef::ERROR { code, msg } => {
+                sink.put(&OP_ERROR.to_be_bytes())?;
+                sink.put(&code.to_be_bytes())?;
+                put_cstr(sink, msg)
+            }
+            TftpPacketRef::OACK(options) => {
+                sink.put(&OP_OACK.to_be_bytes())?;
+                sink.put(options.raw())
+            }
+        }
+    }
+
+    pub fn into_owned(self) -> TftpPacket {
+        match self {
+            TftpPacketRef::RRQ {
+                filename,
+                mode,
+                options,
+            } => TftpPacket::RRQ {
+                filename: filename.to_string(),
+                mode: mode.to_string(),
+                options: options.to_options(),
+            },
+            TftpPacketRef::WRQ {
+                filename,
+                mode,
+                options,
+            } => TftpPacket::WRQ {
+                filename: filename.to_string(),
+                mode: mode.to_string(),
+                options: options.to_options(),
+            },
+            TftpPacketRef::DATA { block, data } => TftpPacket::DATA {
+                block,
+                data: data.to_vec(),
+            },
+            TftpPacketRef::ACK(block) => TftpPacket::ACK(block),
+            TftpPacketRef::ERROR { code, msg } => TftpPacket::ERROR {
+                code,
+                msg: msg.to_string(),
+            },
+            TftpPacketRef::OACK(options) => TftpPacket::OACK(options.to_options()),
+        }
+    }
 }
 
-// 读取以 \0 结尾的 C 风格字符串
-fn read_cstr(buf: &[u8]) -> anyhow::Result<String> {
-    let pos = buf
-        .iter()
-        .position(|&b| b == 0)
-        .ok_or(anyhow!("Missing cstr terminator"))?;
-    let s = str::from_utf8(&buf[..pos])
-        .map_err(|_| anyhow!("Invalid cstr encoding"))?
-        .to_string();
-    Ok(s)
+// 序列化的输出：追加到 Vec 或写入定长缓冲区
+trait Sink {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), PacketError>;
+}
+
+impl Sink for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), PacketError> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+struct SliceSink<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl Sink for SliceSink<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), PacketError> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(PacketError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+fn put_cstr(sink: &mut impl Sink, s: &str) -> Result<(), PacketError> {
+    sink.put(s.as_bytes())?;
+    sink.put(&[0])
+}
+
+fn put_options(sink: &mut impl Sink, options: &TftpOptions) -> Result<(), PacketError> {
+    for (key, value) in options.iter() {
+        put_cstr(sink, key)?;
+        put_cstr(sink, value)?;
+    }
+    Ok(())
 }
 
-// 读取选项（键值对）
-fn read_options(buf: &[u8]) -> anyhow::Result<HashMap<String, String>> {
-    let mut options = HashMap::new();
-    let mut pos = 0;
-    while pos < buf.len() {
-        // 解析选项
-        let key_end = buf[pos..].iter().position(|&b| b == 0).ok_or(anyhow!(
-            "Missing option key terminator"
-        ))?;
-        let key = str::from_utf8(&buf[pos..pos + key_end])
-            .map_err(|_| anyhow!("Invalid option key encoding"))?
-            .to_string();
-        pos += key_end + 1;
-
-        // 解析选项值
-        let value_end = buf[pos..].iter().position(|&b| b == 0).ok_or(anyhow!(
-            "Missing option value terminator"
-        ))?;
-        let value = str::from_utf8(&buf[pos..pos + value_end])
-            .map_err(|_| anyhow!("Invalid option value encoding"))?
-            .to_string();
-        pos += value_end + 1;
-
-        options.insert(key, value);
+// 读取开头的 16 位操作码、块号或错误码，返回其后的内容
+fn split_u16(buf: &[u8]) -> Result<(u16, &[u8]), PacketError> {
+    match buf {
+        [hi, lo, rest @ ..] => Ok((u16::from_be_bytes([*hi, *lo]), rest)),
+        _ => Err(PacketError::Truncated),
     }
-    Ok(options)
+}
+
+// 读取以 \0 结尾的 C 风格字符串，返回其后的内容
+pub(crate) fn split_cstr(buf: &[u8]) -> Result<(&str, &[u8]), PacketError> {
+    let pos = buf
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(PacketError::MissingTerminator)?;
+    let s = str::from_utf8(&buf[..pos]).map_err(|_| PacketError::InvalidUtf8)?;
+    Ok((s, &buf[pos + 1..]))
 }
@@ -0,0 +1,150 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::net::UdpSocket;
+
+// 每次绑定从不同位置开始探测，避免会话都挤在区间开头
+static NEXT_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+// 传输端口范围（闭区间），如 50000:50100 或 50000-50100
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn new(start: u16, end: u16) -> Result<Self, String> {
+        if start == 0 || start > end {
+            return Err(format!("invalid port range: {start}:{end}"));
+        }
+        Ok(Self { start, end })
+    }
+
+    fn len(&self) -> usize {
+        usize::from(self.end - self.start) + 1
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once([':', '-'])
+            .ok_or(format!("invalid port range: {s}, expect START:END"))?;
+        let start = start
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid port: {start}"))?;
+        let end = end
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid port: {end}"))?;
+        Self::new(start, end)
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.start, self.end)
+    }
+}
+
+// 绑定与 peer 同协议族的本地端口，指定范围时在范围内依次尝试
+pub async fn bind_socket(peer: SocketAddr, range: Option<PortRange>) -> io::Result<UdpSocket> {
+    let ip = match peer {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let Some(range) = range else {
+        return UdpSocket::bind((ip, 0)).await;
+    };
+
+    let offset = NEXT_OFFSET.fetch_add(1, Ordering::Relaxed);
+    for i in 0..range.len() {
+        let port = range.start + ((offset + i) % range.len()) as u16;
+        match UdpSocket::bind((ip, port)).await {
+            Ok(socket) => return Ok(socket),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("no free port in range {range}"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 69);
+
+    // 找到两个相邻的空闲端口并占用
+    fn occupy_adjacent_ports() -> (std::net::UdpSocket, std::net::UdpSocket) {
+        loop {
+            let first = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+            let port = first.local_addr().unwrap().port();
+            if port == u16::MAX {
+                continue;
+            }
+            if let Ok(second) = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port + 1)) {
+                return (first, second);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_port_range() {
+        for s in ["50000:50100", "50000-50100", " 50000 : 50100 "] {
+            assert_eq!(s.parse(), Ok(PortRange::new(50000, 50100).unwrap()), "{s}");
+        }
+        let single: PortRange = "69-69".parse().unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single.to_string(), "69:69");
+    }
+
+    #[test]
+    fn invalid_port_ranges_are_rejected() {
+        for s in [
+            "",
+            ":",
+            "-",
+            "50000",
+            "50000:",
+            ":50100",
+            "a:b",
+            "50100:50000",
+            "50100-50000",
+            "0:100",
+            "1:65536",
+            "70000-70001",
+            "-1:5",
+        ] {
+            assert!(s.parse::<PortRange>().is_err(), "{s}");
+        }
+    }
+
+    #[tokio::test]
+    async fn bind_skips_ports_in_use() {
+        let (first, second) = occupy_adjacent_ports();
+        let start = first.local_addr().unwrap().port();
+        let range = PortRange::new(start, start + 1).unwrap();
+
+        // 范围内的端口都被占用
+        let error = bind_socket(PEER, Some(range)).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(error.to_string(), format!("no free port in range {range}"));
+
+        // 无论从哪个端口开始探测，都跳过被占用的端口
+        drop(second);
+        for _ in 0..range.len() {
+            let socket = bind_socket(PEER, Some(range)).await.unwrap();
+            assert_eq!(socket.local_addr().unwrap().port(), start + 1);
+        }
+    }
+}
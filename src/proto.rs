@@ -0,0 +1,453 @@
+// 与运行时无关的协议核心：输入收到的报文和定时器超时，输出待发送的报文和存储操作。
+// Session 是基于 tokio 的驱动实现，其他运行时、模拟器或测试可以按同样方式驱动
+mod client;
+mod receiver;
+mod server;
+
+pub use client::ClientTransfer;
+pub use server::ServerTransfer;
+// 调用 abort 时使用的错误码
+pub use crate::packet::{
+    ERR_ACCESS_VIOLATION, ERR_DISK_FULL, ERR_FILE_EXISTS, ERR_FILE_NOT_FOUND, ERR_ILLEGAL_OP,
+    ERR_NOT_DEFINED, ERR_OPTION,
+};
+
+use crate::congestion::Congestion;
+use crate::options::{TftpOptions, TftpOptionsRef};
+use crate::packet::{TftpPacket, TftpPacketRef};
+use crate::rto::RtoEstimator;
+use crate::sender::{Action, Sender};
+use crate::stats::TransferStats;
+use receiver::Receiver;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const DEF_BLOCK_SIZE: u16 = 512; // RFC 1350
+const MIN_BLOCK_SIZE: u16 = 8; // RFC 2348
+const MAX_BLOCK_SIZE: u16 = 65464; // RFC 2348
+const DEF_WINDOW_SIZE: u16 = 1;
+const DEF_WINDOW_BUDGET: u64 = 4 * 1024 * 1024;
+// 扩展选项：双方都支持时启用选择重传，否则按 RFC 7440 回退 N 帧
+const SELECTIVE_OPTION: &str = "selective";
+
+#[derive(Clone, Debug)]
+pub struct TransferConfig {
+    pub timeout: u64,
+    pub retry: u8,
+    pub gbn: bool,
+    // 根据 RTT 自适应调整超时（RFC 6298），timeout 作为初始值
+    pub adaptive_timeout: bool,
+    // 自适应超时的上下限（ms）
+    pub min_timeout: u64,
+    pub max_timeout: u64,
+    // 选择重传：缓存乱序到达的块，发送端只重传缺失的块（需对端支持）
+    pub selective: bool,
+    // 发送窗口的拥塞控制算法
+    pub congestion: Congestion,
+    // 一个窗口最多缓存的字节数，服务端据此缩小对端请求的 windowsize
+    pub window_budget: u64,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 1000,
+            retry: 3,
+            gbn: false,
+            adaptive_timeout: false,
+            min_timeout: 20,
+            max_timeout: 10000,
+            selective: false,
+            congestion: Congestion::default(),
+            window_budget: DEF_WINDOW_BUDGET,
+        }
+    }
+}
+
+// 驱动方需要执行的操作，借用的数据在下一次调用状态机之前有效
+#[derive(Debug, PartialEq, Eq)]
+pub enum Output<'a> {
+    // 向对端发送报文
+    Transmit(&'a [u8]),
+    // 读取下一块数据（读满给定字节数或到达末尾），通过 push_block 提供
+    Read(usize),
+    // 把收到的数据追加写入存储
+    Write(&'a [u8]),
+    // 数据已全部写入，提交存储（如上传接收者校验内容）。之后才会发出最后的 ACK，
+    // 提交失败时调用 abort，对端收到的是 ERROR
+    Commit,
+    // 等待对端报文，超过给定时间仍未收到则调用 on_timeout
+    Wait(Duration),
+    // 传输完成
+    Done,
+    // 传输失败，需要通知对端的错误已经通过 Transmit 输出
+    Failed(&'a str),
+}
+
+// 一次传输的状态机，客户端和服务端各有实现。
+// 驱动方反复调用 poll 执行输出，直到 Done 或 Failed
+pub trait Transfer {
+    fn poll(&mut self, now: Instant) -> Output<'_>;
+    // 收到对端报文
+    fn on_datagram(&mut self, datagram: &[u8], now: Instant);
+    // Wait 给出的时间内没有收到报文
+    fn on_timeout(&mut self);
+    // 提供 Read 请求的数据，状态机会复制一份，驱动方可以复用缓冲区
+    fn push_block(&mut self, data: &[u8]);
+    // 本地出错（如存储读写失败、服务停机），通知对端并结束传输，尚未输出的操作不再执行
+    fn abort(&mut self, code: u16, msg: String);
+    // 到目前为止的统计，duration 由驱动方填写
+    fn stats(&self) -> TransferStats;
+    // 要传输的数据总字节数（tsize），未知时为 None
+    fn filesize(&self) -> Option<u64> {
+        None
+    }
+    // 数据已全部收到并发出了最后的 ACK，只是在等待对端可能的重传
+    fn is_dallying(&self) -> bool {
+        false
+    }
+}
+
+// 服务端收到的 RRQ/WRQ 请求
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub filename: String,
+    // true 为 WRQ（上传）
+    pub write: bool,
+    pub options: TftpOptions,
+}
+
+impl Request {
+    // 解析监听端口收到的报文，不是请求时返回 None
+    pub fn parse(datagram: &[u8]) -> Option<Self> {
+        match TftpPacket::deserialize(datagram).ok()? {
+            TftpPacket::RRQ {
+                filename, options, ..
+            } => Some(Self {
+                filename,
+                write: false,
+                options,
+            }),
+            TftpPacket::WRQ {
+                filename, options, ..
+            } => Some(Self {
+                filename,
+                write: true,
+                options,
+            }),
+            _ => None,
+        }
+    }
+}
+
+// 协商后的传输参数
+#[derive(Clone, Copy, Debug)]
+struct Params {
+    blksize: u16,
+    windowsize: u16,
+    selective: bool,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            blksize: DEF_BLOCK_SIZE,
+            windowsize: DEF_WINDOW_SIZE,
+            selective: false,
+        }
+    }
+}
+
+// 排队等待输出的操作
+enum Pending {
+    Transmit(Vec<u8>),
+    Write(Vec<u8>),
+    Commit,
+}
+
+// 待输出的操作和传输结果
+#[derive(Default)]
+struct Link {
+    out: VecDeque<Pending>,
+    // 最近一次输出的操作，Output 借用其中的数据
+    current: Option<Pending>,
+    // 已输出完的缓冲区，复用以避免每块分配
+    spare: Vec<Vec<u8>>,
+    // 组装 DATA 报文的缓冲区
+    frame: Vec<u8>,
+    result: Option<Result<(), String>>,
+    // 数据传输阶段之前的统计和协商的参数
+    stats: TransferStats,
+}
+
+impl Link {
+    // 回收上次输出的缓冲区，返回是否还有要输出的操作
+    fn ready(&mut self) -> bool {
+        if let Some(pending) = self.current.take() {
+            self.recycle(pending);
+        }
+        !self.out.is_empty() || self.result.is_some()
+    }
+
+    // 先输出排队的操作，传输结束后一直返回结果
+    fn poll(&mut self) -> Option<Output<'_>> {
+        if !self.ready() {
+            return None;
+        }
+        if let Some(pending) = self.out.pop_front() {
+            return Some(match self.current.insert(pending) {
+                Pending::Transmit(datagram) => Output::Transmit(datagram),
+                Pending::Write(data) => Output::Write(data),
+                Pending::Commit => Output::Commit,
+            });
+        }
+        match &self.result {
+            Some(Ok(())) => Some(Output::Done),
+            Some(Err(msg)) => Some(Output::Failed(msg)),
+            None => None,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.result.is_some()
+    }
+
+    fn recycle(&mut self, pending: Pending) {
+        if let Pending::Transmit(buf) | Pending::Write(buf) = pending {
+            self.spare.push(buf);
+        }
+    }
+
+    // 取一个空的缓冲区
+    fn buffer(&mut self) -> Vec<u8> {
+        let mut buf = self.spare.pop().unwrap_or_default();
+        buf.clear();
+        buf
+    }
+
+    fn transmit(&mut self, pkt: TftpPacketRef) {
+        let mut buf = self.buffer();
+        pkt.append_to(&mut buf);
+        self.out.push_back(Pending::Transmit(buf));
+    }
+
+    fn transmit_raw(&mut self, datagram: &[u8]) {
+        let mut buf = self.buffer();
+        buf.extend_from_slice(datagram);
+        self.out.push_back(Pending::Transmit(buf));
+    }
+
+    fn write(&mut self, data: Vec<u8>) {
+        self.out.push_back(Pending::Write(data));
+    }
+
+    fn commit(&mut self) {
+        self.out.push_back(Pending::Commit);
+    }
+
+    fn finish(&mut self) {
+        self.result.get_or_insert(Ok(()));
+    }
+
+    fn fail(&mut self, msg: String) {
+        self.result.get_or_insert(Err(msg));
+    }
+
+    // 协商阶段超时重发了请求或回应
+    fn on_resend(&mut self) {
+        self.stats.timeouts += 1;
+        self.stats.retransmits += 1;
+    }
+
+    fn set_params(&mut self, params: &Params) {
+        self.stats.blksize = params.blksize;
+        self.stats.windowsize = params.windowsize;
+    }
+
+    // 通知对端错误并结束传输
+    fn error(&mut self, code: u16, msg: String) {
+        if self.is_finished() {
+            return;
+        }
+        self.transmit(TftpPacketRef::ERROR { code, msg: &msg });
+        self.fail(msg);
+    }
+
+    // 本地出错时丢弃排队的操作（如提交后才发出的最后的 ACK），
+    // 已收完数据但还有操作未输出的传输同样改为失败
+    fn abort(&mut self, code: u16, msg: String) {
+        if let Some(Err(_)) = self.result {
+            return;
+        }
+        while let Some(pending) = self.out.pop_front() {
+            self.recycle(pending);
+        }
+        self.result = None;
+        self.error(code, msg);
+    }
+}
+
+// 协商完成后的数据传输阶段
+enum Data {
+    Send(Sender),
+    Recv(Receiver),
+}
+
+impl Data {
+    fn poll<'a>(&'a mut self, link: &'a mut Link, now: Instant) -> Output<'a> {
+        match self {
+            Data::Send(sender) => match sender.poll(now) {
+                Action::Read => Output::Read(sender.blksize()),
+                Action::Send { block, data } => {
+                    link.frame.clear();
+                    TftpPacketRef::DATA { block, data }.append_to(&mut link.frame);
+                    Output::Transmit(&link.frame)
+                }
+                Action::Wait => Output::Wait(sender.timeout()),
+                Action::Done => {
+                    link.finish();
+                    Output::Done
+                }
+                Action::Fail(msg) => {
+                    link.error(ERR_NOT_DEFINED, msg);
+                    link.poll().unwrap()
+                }
+            },
+            Data::Recv(receiver) => Output::Wait(receiver.timeout()),
+        }
+    }
+
+    fn on_packet(&mut self, link: &mut Link, pkt: TftpPacketRef, now: Instant) {
+        match self {
+            Data::Send(sender) => match pkt {
+                TftpPacketRef::ACK(block) => sender.on_ack(block, now),
+                // 对端重传的 OACK，协商已经完成
+                TftpPacketRef::OACK(_) => (),
+                TftpPacketRef::ERROR { code, msg } => {
+                    link.fail(format!("Get error packet: code: {code}, msg: {msg}"))
+                }
+                _ => link.fail("Not ack packet".to_string()),
+            },
+            Data::Recv(receiver) => receiver.on_packet(link, pkt, now),
+        }
+    }
+
+    fn on_timeout(&mut self, link: &mut Link) {
+        match self {
+            Data::Send(sender) => sender.on_timeout(),
+            Data::Recv(receiver) => receiver.on_timeout(link),
+        }
+    }
+
+    fn push_block(&mut self, data: &[u8]) {
+        if let Data::Send(sender) = self {
+            sender.push_block(data);
+        }
+    }
+
+    fn is_dallying(&self) -> bool {
+        matches!(self, Data::Recv(receiver) if receiver.is_dallying())
+    }
+
+    fn add_stats(&self, stats: &mut TransferStats) {
+        match self {
+            Data::Send(sender) => sender.add_stats(stats),
+            Data::Recv(receiver) => receiver.add_stats(stats),
+        }
+    }
+}
+
+fn new_sender(params: Params, config: &TransferConfig, rto: RtoEstimator) -> Sender {
+    let mut gbn = config.gbn;
+    let mut windowsize = params.windowsize;
+    if gbn && windowsize == 1 {
+        windowsize = 4;
+    } else {
+        gbn = false;
+    }
+    let cc = config.congestion.build(windowsize);
+    Sender::new(params.blksize, windowsize, config.retry, rto, cc)
+        .with_gbn(gbn)
+        .with_selective(params.selective)
+}
+
+// 服务端协商请求中的选项，返回需要在 OACK 中确认的选项。
+// filesize 为 RRQ 要发送的文件大小，未知时不回应 tsize
+fn negotiate_options(
+    options: &TftpOptions,
+    is_rrq: bool,
+    filesize: Option<u64>,
+    config: &TransferConfig,
+    params: &mut Params,
+) -> Result<TftpOptions, String> {
+    let mut nego_options = TftpOptions::new();
+    if let Some(blksize) = options.blksize().map_err(|e| e.to_string())? {
+        params.blksize = blksize.clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE);
+        nego_options.set_blksize(params.blksize);
+    }
+    if let Some(windowsize) = options.windowsize().map_err(|e| e.to_string())? {
+        if windowsize == 0 {
+            return Err(format!("Invalid windowsize: {windowsize}"));
+        }
+        // 发送端缓存整个窗口的数据，接收端最多缓存同样多的乱序块
+        let limit = config.window_budget / u64::from(params.blksize);
+        params.windowsize = windowsize.min(limit.clamp(1, u64::from(u16::MAX)) as u16);
+        nego_options.set_windowsize(params.windowsize);
+    }
+    if config.selective && options.get(SELECTIVE_OPTION) == Some("1") {
+        params.selective = true;
+        nego_options.insert(SELECTIVE_OPTION, "1");
+    }
+    if is_rrq {
+        // RRQ 中的 tsize 为 0，回应实际的文件大小
+        if let Some(filesize) = filesize.filter(|_| options.contains_key("tsize")) {
+            nego_options.set_tsize(filesize);
+        }
+    } else if let Some(tsize) = options.tsize().map_err(|e| e.to_string())? {
+        nego_options.set_tsize(tsize);
+    }
+    Ok(nego_options)
+}
+
+// 客户端请求中携带的选项
+fn request_options(
+    blksize: u16,
+    windowsize: u16,
+    tsize: Option<u64>,
+    config: &TransferConfig,
+) -> TftpOptions {
+    let mut options = TftpOptions::new();
+    if blksize != DEF_BLOCK_SIZE {
+        options.set_blksize(blksize);
+    }
+    if windowsize != DEF_WINDOW_SIZE {
+        options.set_windowsize(windowsize);
+    }
+    if let Some(tsize) = tsize {
+        options.set_tsize(tsize);
+    }
+    if config.selective {
+        options.insert(SELECTIVE_OPTION, "1");
+    }
+    options
+}
+
+// 客户端解析 OACK，未确认的选项使用默认值，返回对端给出的 tsize
+fn accept_oack(opts: &TftpOptionsRef, params: &mut Params) -> Result<Option<u64>, String> {
+    let opts = opts.to_options();
+    *params = Params::default();
+    if let Some(blksize) = opts.blksize().map_err(|e| e.to_string())? {
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&blksize) {
+            return Err(format!("Invalid blksize: {blksize}"));
+        }
+        params.blksize = blksize;
+    }
+    if let Some(windowsize) = opts.windowsize().map_err(|e| e.to_string())? {
+        if windowsize == 0 {
+            return Err(format!("Invalid windowsize: {windowsize}"));
+        }
+        params.windowsize = windowsize;
+    }
+    params.selective = opts.get(SELECTIVE_OPTION) == Some("1");
+    opts.tsize().map_err(|e| e.to_string())
+}
@@ -0,0 +1,247 @@
+use super::{
+    Data, Link, Output, Params, Transfer, TransferConfig, accept_oack, new_sender, request_options,
+};
+use crate::packet::{ERR_OPTION, TftpPacket, TftpPacketRef};
+use crate::proto::receiver::Receiver;
+use crate::rto::RtoEstimator;
+use crate::stats::TransferStats;
+use log::{info, warn};
+use std::mem;
+use std::time::Instant;
+
+// 每次传输只有一个，不必为缩小体积装箱
+#[allow(clippy::large_enum_variant)]
+enum Phase {
+    // 等待服务端回应 RRQ/WRQ
+    Request {
+        request: Vec<u8>,
+        rto: RtoEstimator,
+        retries: u8,
+        sent_at: Option<Instant>,
+        resend: bool,
+    },
+    Data(Data),
+    Closed,
+}
+
+// 客户端一次传输的状态机。第一个报文需发往服务端的监听地址，
+// 之后的报文都发往首个回应的来源地址（服务端的传输端口）
+pub struct ClientTransfer {
+    config: TransferConfig,
+    link: Link,
+    phase: Phase,
+    is_get: bool,
+    filesize: Option<u64>,
+}
+
+impl ClientTransfer {
+    // 下载文件（RRQ）
+    pub fn get(filename: &str, blksize: u16, windowsize: u16, config: TransferConfig) -> Self {
+        let pkt = TftpPacket::RRQ {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options: request_options(blksize, windowsize, Some(0), &config),
+        };
+        Self::new(pkt, true, None, config)
+    }
+
+    // 上传文件（WRQ），filesize 通过 tsize 选项告知服务端，未知时不携带 tsize
+    pub fn put(
+        filename: &str,
+        blksize: u16,
+        windowsize: u16,
+        filesize: Option<u64>,
+        config: TransferConfig,
+    ) -> Self {
+        let pkt = TftpPacket::WRQ {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options: request_options(blksize, windowsize, filesize, &config),
+        };
+        Self::new(pkt, false, filesize, config)
+    }
+
+    fn new(
+        request: TftpPacket,
+        is_get: bool,
+        filesize: Option<u64>,
+        config: TransferConfig,
+    ) -> Self {
+        let rto = RtoEstimator::new(&config);
+        Self {
+            config,
+            link: Link::default(),
+            phase: Phase::Request {
+                request: request.serialize(),
+                rto,
+                retries: 0,
+                sent_at: None,
+                resend: true,
+            },
+            is_get,
+            filesize,
+        }
+    }
+
+    fn on_response(&mut self, pkt: TftpPacketRef, now: Instant) {
+        let Phase::Request {
+            mut rto,
+            retries,
+            sent_at,
+            ..
+        } = mem::replace(&mut self.phase, Phase::Closed)
+        else {
+            unreachable!()
+        };
+        if retries == 0
+            && let Some(sent_at) = sent_at
+        {
+            rto.on_sample(now.saturating_duration_since(sent_at));
+        }
+        let mut params = Params::default();
+        let data = match (self.is_get, pkt) {
+            (_, TftpPacketRef::OACK(opts)) => {
+                match accept_oack(&opts, &mut params) {
+                    Ok(Some(tsize)) if self.is_get => self.filesize = Some(tsize),
+                    Ok(_) => (),
+                    // RFC 2347：不接受 OACK 中的选项时以错误码 8 终止
+                    Err(e) => return self.link.error(ERR_OPTION, e),
+                }
+                if self.is_get {
+                    info!("negotiated: {:?}", opts);
+                    self.link.transmit(TftpPacketRef::ACK(0));
+                    Data::Recv(Receiver::new(params, &self.config, rto))
+                } else {
+                    info!("WRQ negotiated: {:?}", opts);
+                    Data::Send(new_sender(params, &self.config, rto))
+                }
+            }
+            // 服务端不支持选项，直接回应 DATA#1
+            (true, TftpPacketRef::DATA { block, data }) => {
+                let mut receiver = Receiver::new(params, &self.config, rto);
+                receiver.on_data(&mut self.link, block, data);
+                Data::Recv(receiver)
+            }
+            (false, TftpPacketRef::ACK(0)) => Data::Send(new_sender(params, &self.config, rto)),
+            (_, TftpPacketRef::ERROR { code, msg }) => {
+                return self
+                    .link
+                    .fail(format!("Server error: code={code}, msg={msg}"));
+            }
+            _ => {
+                return self.link.fail(format!(
+                    "Unexpected packet during {} negotiation",
+                    self.request_name()
+                ));
+            }
+        };
+        self.link.set_params(&params);
+        self.phase = Phase::Data(data);
+    }
+
+    fn request_name(&self) -> &'static str {
+        if self.is_get { "RRQ" } else { "WRQ" }
+    }
+}
+
+impl Transfer for ClientTransfer {
+    fn poll(&mut self, now: Instant) -> Output<'_> {
+        if self.link.ready() {
+            return self.link.poll().unwrap();
+        }
+        match &mut self.phase {
+            Phase::Request {
+                request,
+                rto,
+                sent_at,
+                resend,
+                ..
+            } => {
+                if *resend {
+                    *resend = false;
+                    sent_at.get_or_insert(now);
+                    return Output::Transmit(request);
+                }
+                Output::Wait(rto.timeout())
+            }
+            Phase::Data(data) => data.poll(&mut self.link, now),
+            Phase::Closed => unreachable!("closed transfer must have a result"),
+        }
+    }
+
+    fn on_datagram(&mut self, datagram: &[u8], now: Instant) {
+        if self.link.is_finished() {
+            return;
+        }
+        let pkt = match TftpPacketRef::deserialize(datagram) {
+            Ok(pkt) => pkt,
+            Err(e) => {
+                warn!("malformed packet ignored: {e}");
+                return;
+            }
+        };
+        // 服务端不会回应空的 OACK（不支持选项时直接回应 DATA#1 或 ACK#0），
+        // 多半是被截断的报文，按默认参数继续会与服务端协商的参数不一致
+        if let (Phase::Request { .. }, TftpPacketRef::OACK(opts)) = (&self.phase, &pkt)
+            && opts.is_empty()
+        {
+            warn!("empty OACK ignored");
+            return;
+        }
+        match &mut self.phase {
+            Phase::Request { .. } => self.on_response(pkt, now),
+            Phase::Data(data) => data.on_packet(&mut self.link, pkt, now),
+            Phase::Closed => (),
+        }
+    }
+
+    fn on_timeout(&mut self) {
+        if self.link.is_finished() {
+            return;
+        }
+        let name = self.request_name();
+        match &mut self.phase {
+            Phase::Request {
+                rto,
+                retries,
+                resend,
+                ..
+            } => {
+                rto.on_timeout();
+                *retries += 1;
+                if *retries >= self.config.retry {
+                    self.link.fail(format!("Max retries reached during {name}"));
+                } else {
+                    warn!("timeout, resending {name}");
+                    self.link.on_resend();
+                    *resend = true;
+                }
+            }
+            Phase::Data(data) => data.on_timeout(&mut self.link),
+            Phase::Closed => (),
+        }
+    }
+
+    fn push_block(&mut self, data: &[u8]) {
+        if let Phase::Data(phase) = &mut self.phase {
+            phase.push_block(data);
+        }
+    }
+
+    fn abort(&mut self, code: u16, msg: String) {
+        self.link.abort(code, msg);
+    }
+
+    // 下载时为服务端通过 tsize 告知的大小
+    fn filesize(&self) -> Option<u64> {
+        self.filesize
+    }
+
+    fn stats(&self) -> TransferStats {
+        let mut stats = self.link.stats.clone();
+        if let Phase::Data(data) = &self.phase {
+            data.add_stats(&mut stats);
+        }
+        stats
+    }
+}
@@ -0,0 +1,269 @@
+use super::{Link, Params, TransferConfig};
+use crate::packet::{ERR_NOT_DEFINED, TftpPacketRef};
+use crate::rto::RtoEstimator;
+use crate::stats::TransferStats;
+use log::warn;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// 缓存范围小于块号空间的一半，否则回绕前的旧块会被当作之后的块
+const MAX_REORDER: u16 = 32767;
+
+// 接收端状态机：按序输出收到的数据，在窗口边界或发现缺口时 ACK
+pub(super) struct Receiver {
+    blksize: usize,
+    windowsize: u16,
+    selective: bool,
+    retry: u8,
+    rto: RtoEstimator,
+    expected: u16,
+    retries: u8,
+    window_count: u16,
+    buffered: HashMap<u16, Vec<u8>>,
+    // GBN 发送端的实际窗口可能大于协商值，缓存范围留出余量，
+    // 同时不超过块号空间的一半和字节预算
+    reorder_limit: u16,
+    // 用于首个 RTT 采样的起始时间
+    sample_from: Option<Instant>,
+    // 收到第一块之前超时重发的报文（服务端的 OACK/ACK#0）
+    initial: Option<Vec<u8>>,
+    dally: bool,
+    // 已收到的最后一块，等待期间对端重传时再次 ACK
+    last: Option<u16>,
+    // 统计：已写入的数据、超时重发的报文和超时次数
+    bytes: u64,
+    blocks: u64,
+    retransmits: u64,
+    timeouts: u64,
+}
+
+impl Receiver {
+    pub(super) fn new(params: Params, config: &TransferConfig, rto: RtoEstimator) -> Self {
+        let budget = config.window_budget / u64::from(params.blksize);
+        let reorder_limit = params
+            .windowsize
+            .saturating_mul(2)
+            .max(8)
+            .min(budget.min(u64::from(MAX_REORDER)) as u16);
+        Self {
+            blksize: usize::from(params.blksize),
+            windowsize: params.windowsize.max(1),
+            selective: params.selective,
+            retry: config.retry,
+            rto,
+            expected: 1,
+            retries: 0,
+            window_count: 0,
+            buffered: HashMap::new(),
+            reorder_limit,
+            sample_from: None,
+            initial: None,
+            dally: false,
+            last: None,
+            bytes: 0,
+            blocks: 0,
+            retransmits: 0,
+            timeouts: 0,
+        }
+    }
+
+    // 以 reply 发出到 DATA#1 到达的间隔作为首个 RTT 采样，超时未收到数据时重发 reply
+    pub(super) fn with_initial(mut self, reply: Vec<u8>, now: Instant) -> Self {
+        self.initial = Some(reply);
+        self.sample_from = Some(now);
+        self
+    }
+
+    // 收到最后一块后继续等待，直到两个超时周期内没有重传才结束：
+    // 最后的 ACK 丢失时对端会重传，需要再次确认（RFC 1350 第 6 节）
+    pub(super) fn with_dally(mut self) -> Self {
+        self.dally = true;
+        self
+    }
+
+    pub(super) fn add_stats(&self, stats: &mut TransferStats) {
+        stats.bytes += self.bytes;
+        stats.blocks += self.blocks;
+        stats.retransmits += self.retransmits;
+        stats.timeouts += self.timeouts;
+        stats.rtt = self.rto.srtt().or(stats.rtt);
+    }
+
+    pub(super) fn is_dallying(&self) -> bool {
+        self.last.is_some()
+    }
+
+    pub(super) fn timeout(&self) -> Duration {
+        match self.last {
+            Some(_) => self.rto.timeout() * 2,
+            None => self.rto.timeout(),
+        }
+    }
+
+    pub(super) fn on_packet(&mut self, link: &mut Link, pkt: TftpPacketRef, now: Instant) {
+        self.retries = 0;
+        if let Some(sent_at) = self.sample_from.take() {
+            self.rto.on_sample(now.saturating_duration_since(sent_at));
+        }
+        match pkt {
+            TftpPacketRef::DATA { block, data } => self.on_data(link, block, data),
+            // 数据已全部收到，等待期间的错误不影响结果
+            TftpPacketRef::ERROR { .. } if self.last.is_some() => link.finish(),
+            TftpPacketRef::ERROR { code, msg } => {
+                link.fail(format!("Peer error: code={code}, msg={msg}"))
+            }
+            _ => (),
+        }
+    }
+
+    pub(super) fn on_data(&mut self, link: &mut Link, mut block: u16, data: &[u8]) {
+        // 对端没有收到最后的 ACK，重传的可能是窗口内更早的块
+        if let Some(last) = self.last {
+            self.ack(link, last);
+            return;
+        }
+        if block != self.expected {
+            // 选择重传：缓存窗口内提前到达的块
+            if self.selective
+                && block.wrapping_sub(self.expected) < self.reorder_limit
+                && !self.buffered.contains_key(&block)
+            {
+                let mut buf = link.buffer();
+                buf.extend_from_slice(data);
+                self.buffered.insert(block, buf);
+            }
+            self.ack(link, self.expected.wrapping_sub(1));
+            return;
+        }
+
+        self.initial = None;
+        let mut buf = link.buffer();
+        buf.extend_from_slice(data);
+        loop {
+            let is_last = buf.len() < self.blksize;
+            self.bytes += buf.len() as u64;
+            self.blocks += 1;
+            link.write(buf);
+            self.window_count += 1;
+            if is_last {
+                // 存储提交成功后才确认最后一块
+                link.commit();
+                self.ack(link, block);
+                if self.dally {
+                    self.last = Some(block);
+                } else {
+                    link.finish();
+                }
+                return;
+            }
+            self.expected = self.expected.wrapping_add(1);
+            // 缺口补齐后顺序写入已缓存的后续块
+            match self.buffered.remove(&self.expected) {
+                Some(next) => {
+                    block = self.expected;
+                    buf = next;
+                }
+                None => break,
+            }
+        }
+
+        // RFC 7440: only ACK at window boundary or last packet
+        if self.window_count >= self.windowsize {
+            self.ack(link, block);
+        } else if !self.buffered.is_empty() {
+            // 后面还有缺口，立即告知发送端
+            self.ack(link, self.expected.wrapping_sub(1));
+        }
+    }
+
+    pub(super) fn on_timeout(&mut self, link: &mut Link) {
+        if self.last.is_some() {
+            link.finish();
+            return;
+        }
+        warn!("timeout waiting for DATA#{}", self.expected);
+        self.timeouts += 1;
+        self.rto.on_timeout();
+        self.sample_from = None;
+        self.retries += 1;
+        if self.retries >= self.retry {
+            link.error(ERR_NOT_DEFINED, "Max retries reached".to_string());
+            return;
+        }
+        self.retransmits += 1;
+        match &self.initial {
+            Some(reply) => {
+                link.transmit_raw(reply);
+                self.window_count = 0;
+            }
+            None => self.ack(link, self.expected.wrapping_sub(1)),
+        }
+    }
+
+    fn ack(&mut self, link: &mut Link, block: u16) {
+        link.transmit(TftpPacketRef::ACK(block));
+        self.window_count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLKSIZE: u16 = 4;
+
+    fn receiver(windowsize: u16, window_budget: u64) -> Receiver {
+        let config = TransferConfig {
+            window_budget,
+            ..Default::default()
+        };
+        let params = Params {
+            blksize: BLKSIZE,
+            windowsize,
+            selective: true,
+        };
+        Receiver::new(params, &config, RtoEstimator::new(&config))
+    }
+
+    fn buffered(receiver: &Receiver) -> Vec<u16> {
+        let mut blocks: Vec<u16> = receiver.buffered.keys().copied().collect();
+        blocks.sort();
+        blocks
+    }
+
+    // 窗口很大时，重复的旧块回绕后的差值仍在缓存范围之外，不会被当作之后的块
+    #[test]
+    fn stale_blocks_are_not_buffered() {
+        let mut link = Link::default();
+        let mut receiver = receiver(40000, u64::MAX);
+        assert_eq!(receiver.reorder_limit, MAX_REORDER);
+        for block in 1..=20 {
+            receiver.on_data(&mut link, block, &[block as u8; 4]);
+        }
+        receiver.on_data(&mut link, 10, &[0; 4]);
+        receiver.on_data(&mut link, 21u16.wrapping_sub(MAX_REORDER), &[0; 4]);
+        assert!(buffered(&receiver).is_empty());
+
+        receiver.on_data(&mut link, 23, &[23; 4]);
+        receiver.on_data(&mut link, 20 + MAX_REORDER, &[0; 4]);
+        assert_eq!(buffered(&receiver), [23, 20 + MAX_REORDER]);
+    }
+
+    // 缓存的乱序块不超过字节预算
+    #[test]
+    fn reorder_buffer_is_bounded_by_budget() {
+        let mut link = Link::default();
+        let mut receiver = receiver(64, 8 * u64::from(BLKSIZE));
+        assert_eq!(receiver.reorder_limit, 8);
+        for block in 2..=12 {
+            receiver.on_data(&mut link, block, &[block as u8; 4]);
+        }
+        assert_eq!(buffered(&receiver), (2..=8).collect::<Vec<_>>());
+
+        // 补齐缺口后按顺序写入缓存的块
+        receiver.on_data(&mut link, 1, &[1; 4]);
+        assert!(buffered(&receiver).is_empty());
+        assert_eq!(receiver.expected, 9);
+        assert_eq!(receiver.blocks, 8);
+    }
+}
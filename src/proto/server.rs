@@ -0,0 +1,282 @@
+use super::{Data, Link, Output, Params, Transfer, TransferConfig, negotiate_options, new_sender};
+use crate::options::TftpOptions;
+use crate::packet::{ERR_ILLEGAL_OP, ERR_NOT_DEFINED, ERR_OPTION, TftpPacket, TftpPacketRef};
+use crate::proto::receiver::Receiver;
+use crate::rto::RtoEstimator;
+use crate::stats::TransferStats;
+use log::{info, warn};
+use std::mem;
+use std::time::Instant;
+
+// 每次传输只有一个，不必为缩小体积装箱
+#[allow(clippy::large_enum_variant)]
+enum Phase {
+    // 尚未处理请求中的选项，第一次 poll 时协商
+    Request {
+        options: TftpOptions,
+        is_rrq: bool,
+    },
+    // RRQ：等待对端确认 OACK
+    Oack {
+        oack: Vec<u8>,
+        params: Params,
+        rto: RtoEstimator,
+        retries: u8,
+        sent_at: Option<Instant>,
+        resend: bool,
+    },
+    // WRQ：尚未回应 OACK/ACK#0
+    Accept {
+        reply: Vec<u8>,
+        params: Params,
+        rto: RtoEstimator,
+    },
+    Data(Data),
+    Closed,
+}
+
+// 服务端一次传输的状态机，请求报文由调用方解析后传入
+pub struct ServerTransfer {
+    config: TransferConfig,
+    link: Link,
+    phase: Phase,
+    // 协商结果之外需要在 OACK 中确认的选项
+    accepted: TftpOptions,
+    // 在 OACK 中确认的选项，协商前或选项无效时为 None
+    negotiated: Option<TftpOptions>,
+    // RRQ 要发送的文件大小，WRQ 中对端告知的 tsize
+    filesize: Option<u64>,
+}
+
+impl ServerTransfer {
+    // 处理 RRQ，filesize 未知时不回应 tsize 选项
+    pub fn read(options: TftpOptions, filesize: Option<u64>, config: TransferConfig) -> Self {
+        Self::new(options, filesize, true, config)
+    }
+
+    // 处理 WRQ
+    pub fn write(options: TftpOptions, config: TransferConfig) -> Self {
+        Self::new(options, None, false, config)
+    }
+
+    fn new(
+        options: TftpOptions,
+        filesize: Option<u64>,
+        is_rrq: bool,
+        config: TransferConfig,
+    ) -> Self {
+        Self {
+            config,
+            link: Link::default(),
+            phase: Phase::Request { options, is_rrq },
+            accepted: TftpOptions::new(),
+            negotiated: None,
+            filesize,
+        }
+    }
+
+    // 在 OACK 中额外确认的选项（如自定义选项），与已协商的选项同名时以协商结果为准
+    pub fn with_options(mut self, accepted: TftpOptions) -> Self {
+        self.accepted = accepted;
+        self
+    }
+
+    // 协商请求中的选项（否则在第一次 poll 时协商），返回在 OACK 中确认的选项，
+    // 为空时不发送 OACK，选项无效时返回 None
+    pub fn negotiated(&mut self) -> Option<&TftpOptions> {
+        if let Phase::Request { .. } = self.phase {
+            self.negotiate();
+        }
+        self.negotiated.as_ref()
+    }
+
+    fn negotiate(&mut self) {
+        let Phase::Request { options, is_rrq } = mem::replace(&mut self.phase, Phase::Closed)
+        else {
+            unreachable!()
+        };
+        let mut params = Params::default();
+        let rto = RtoEstimator::new(&self.config);
+        let mut nego_options =
+            match negotiate_options(&options, is_rrq, self.filesize, &self.config, &mut params) {
+                Ok(nego_options) => nego_options,
+                Err(e) => return self.link.error(ERR_OPTION, e),
+            };
+        for (key, value) in self.accepted.iter() {
+            if !nego_options.contains_key(key) {
+                nego_options.insert(key, value);
+            }
+        }
+        self.link.set_params(&params);
+        if !is_rrq {
+            self.filesize = nego_options.tsize().ok().flatten();
+        }
+        self.negotiated = Some(nego_options.clone());
+        self.phase = if !is_rrq {
+            let reply = if nego_options.is_empty() {
+                TftpPacket::ACK(0)
+            } else {
+                info!("wrq nego: {:?}", nego_options);
+                TftpPacket::OACK(nego_options)
+            };
+            Phase::Accept {
+                reply: reply.serialize(),
+                params,
+                rto,
+            }
+        } else if nego_options.is_empty() {
+            Phase::Data(Data::Send(new_sender(params, &self.config, rto)))
+        } else {
+            info!("nego: {:?}", nego_options);
+            Phase::Oack {
+                oack: TftpPacket::OACK(nego_options).serialize(),
+                params,
+                rto,
+                retries: 0,
+                sent_at: None,
+                resend: true,
+            }
+        };
+    }
+
+    // WRQ：回应 OACK/ACK#0 并开始接收数据
+    fn accept(&mut self, now: Instant) {
+        let Phase::Accept { reply, params, rto } = mem::replace(&mut self.phase, Phase::Closed)
+        else {
+            unreachable!()
+        };
+        self.link.transmit_raw(&reply);
+        let receiver = Receiver::new(params, &self.config, rto)
+            .with_initial(reply, now)
+            .with_dally();
+        self.phase = Phase::Data(Data::Recv(receiver));
+    }
+}
+
+impl Transfer for ServerTransfer {
+    fn poll(&mut self, now: Instant) -> Output<'_> {
+        if let Phase::Request { .. } = self.phase {
+            self.negotiate();
+        }
+        if let Phase::Accept { .. } = self.phase {
+            self.accept(now);
+        }
+        if self.link.ready() {
+            return self.link.poll().unwrap();
+        }
+        match &mut self.phase {
+            Phase::Oack {
+                oack,
+                rto,
+                sent_at,
+                resend,
+                ..
+            } => {
+                if *resend {
+                    *resend = false;
+                    *sent_at = Some(now);
+                    return Output::Transmit(oack);
+                }
+                Output::Wait(rto.timeout())
+            }
+            Phase::Data(data) => data.poll(&mut self.link, now),
+            Phase::Request { .. } | Phase::Accept { .. } | Phase::Closed => {
+                unreachable!("closed transfer must have a result")
+            }
+        }
+    }
+
+    fn on_datagram(&mut self, datagram: &[u8], now: Instant) {
+        if self.link.is_finished() {
+            return;
+        }
+        let pkt = match TftpPacketRef::deserialize(datagram) {
+            Ok(pkt) => pkt,
+            Err(e) => {
+                warn!("malformed packet ignored: {e}");
+                return;
+            }
+        };
+        match &mut self.phase {
+            Phase::Oack {
+                retries, sent_at, ..
+            } => match pkt {
+                TftpPacketRef::ACK(0) => {
+                    let sample = (*retries == 0).then_some(*sent_at).flatten();
+                    let Phase::Oack {
+                        params, mut rto, ..
+                    } = mem::replace(&mut self.phase, Phase::Closed)
+                    else {
+                        unreachable!()
+                    };
+                    if let Some(sent_at) = sample {
+                        rto.on_sample(now.saturating_duration_since(sent_at));
+                    }
+                    self.phase = Phase::Data(Data::Send(new_sender(params, &self.config, rto)));
+                }
+                TftpPacketRef::ACK(block) => self
+                    .link
+                    .error(ERR_ILLEGAL_OP, format!("expect block #0, but #{block}")),
+                TftpPacketRef::ERROR { code, msg } => self
+                    .link
+                    .fail(format!("Get error packet: code: {code}, msg: {msg}")),
+                _ => self.link.fail("Not ack packet".to_string()),
+            },
+            Phase::Data(data) => data.on_packet(&mut self.link, pkt, now),
+            Phase::Request { .. } | Phase::Accept { .. } | Phase::Closed => (),
+        }
+    }
+
+    fn on_timeout(&mut self) {
+        if self.link.is_finished() {
+            return;
+        }
+        match &mut self.phase {
+            Phase::Oack {
+                rto,
+                retries,
+                resend,
+                ..
+            } => {
+                warn!("timeout waiting for ACK#0");
+                rto.on_timeout();
+                *retries += 1;
+                if *retries >= self.config.retry {
+                    self.link
+                        .error(ERR_NOT_DEFINED, "Max retries reached".to_string());
+                } else {
+                    self.link.on_resend();
+                    *resend = true;
+                }
+            }
+            Phase::Data(data) => data.on_timeout(&mut self.link),
+            Phase::Request { .. } | Phase::Accept { .. } | Phase::Closed => (),
+        }
+    }
+
+    fn push_block(&mut self, data: &[u8]) {
+        if let Phase::Data(phase) = &mut self.phase {
+            phase.push_block(data);
+        }
+    }
+
+    fn abort(&mut self, code: u16, msg: String) {
+        self.link.abort(code, msg);
+    }
+
+    fn filesize(&self) -> Option<u64> {
+        self.filesize
+    }
+
+    fn is_dallying(&self) -> bool {
+        matches!(&self.phase, Phase::Data(data) if data.is_dallying())
+    }
+
+    fn stats(&self) -> TransferStats {
+        let mut stats = self.link.stats.clone();
+        if let Phase::Data(data) = &self.phase {
+            data.add_stats(&mut stats);
+        }
+        stats
+    }
+}
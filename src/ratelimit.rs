@@ -0,0 +1,155 @@
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant, sleep};
+
+// 令牌桶最多积攒 100ms 的突发流量
+const BURST_MILLIS: u64 = 100;
+
+// 令牌桶限速器，按字节计费
+// 令牌不足时允许透支，调用方睡眠到欠账还清为止，单个大报文不会被永久阻塞
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: u64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    // rate 单位为字节/秒
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1);
+        let burst = (rate.saturating_mul(BURST_MILLIS) / 1000).max(1) as f64;
+        Self {
+            rate,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    // 申请发送 bytes 字节，令牌不足时异步等待
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.rate as f64).min(self.burst);
+            bucket.last = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.rate as f64)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+// 解析速率字符串，支持 K/M/G 后缀（1024 进制），如 512K、10M
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (num, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((pos, _)) => s.split_at(pos),
+        None => (s, ""),
+    };
+    let num: u64 = num.parse().map_err(|_| format!("invalid rate: {s}"))?;
+    let scale: u64 = match unit.to_ascii_uppercase().trim_end_matches("B") {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid rate unit: {unit}")),
+    };
+    match num.checked_mul(scale) {
+        Some(0) => Err("rate must be greater than 0".to_string()),
+        Some(rate) => Ok(rate),
+        None => Err(format!("rate too large: {s}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 时钟暂停时 sleep 立即推进虚拟时间，误差来自计时器 1ms 的精度
+    fn assert_elapsed(start: Instant, expected: Duration) {
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= expected && elapsed < expected + Duration::from_millis(5),
+            "{elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn parse_rate_units() {
+        assert_eq!(parse_rate("1000"), Ok(1000));
+        assert_eq!(parse_rate("8b"), Ok(8));
+        assert_eq!(parse_rate("512K"), Ok(512 * 1024));
+        assert_eq!(parse_rate(" 64KB "), Ok(64 * 1024));
+        assert_eq!(parse_rate("10m"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_rate("2G"), Ok(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn parse_rate_rejects_bad_input() {
+        for s in ["", "K", "abc", "-1", "1.5M", "10T", "10KK"] {
+            assert!(parse_rate(s).is_err(), "{s}");
+        }
+        for s in ["0", "0K"] {
+            assert_eq!(
+                parse_rate(s),
+                Err("rate must be greater than 0".to_string())
+            );
+        }
+        assert_eq!(
+            parse_rate("99999999999G"),
+            Err("rate too large: 99999999999G".to_string())
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire_paces_to_rate() {
+        // 突发上限为 1000 字节
+        let limiter = RateLimiter::new(10_000);
+        let start = Instant::now();
+        limiter.acquire(1000).await;
+        assert_elapsed(start, Duration::ZERO);
+        // 桶空后每 1000 字节需要 100ms
+        for _ in 0..5 {
+            limiter.acquire(1000).await;
+        }
+        assert_elapsed(start, Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn large_acquire_overdraws_bucket() {
+        // 超过突发上限的报文不会被永久阻塞，只需等待欠账还清
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        limiter.acquire(600).await;
+        assert_elapsed(start, Duration::from_millis(500));
+        // 欠账已还清，下一次只为自身等待
+        let start = Instant::now();
+        limiter.acquire(100).await;
+        assert_elapsed(start, Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_tokens_are_capped_at_burst() {
+        let limiter = RateLimiter::new(10_000);
+        sleep(Duration::from_secs(10)).await;
+        let start = Instant::now();
+        limiter.acquire(3000).await;
+        assert_elapsed(start, Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::proto::TransferConfig;
+use std::time::Duration;
+
+// 时钟粒度 G（RFC 6298）
+const CLOCK_GRANULARITY: Duration = Duration::from_millis(1);
+// 连续超时的最大退避次数
+const MAX_BACKOFF: u32 = 16;
+
+// 重传超时估计（RFC 6298）
+// 非自适应模式下始终返回固定的 config.timeout
+#[derive(Clone, Debug)]
+pub struct RtoEstimator {
+    adaptive: bool,
+    min: Duration,
+    max: Duration,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+    backoff: u32,
+}
+
+impl RtoEstimator {
+    pub fn new(config: &TransferConfig) -> Self {
+        let min = Duration::from_millis(config.min_timeout);
+        let max = Duration::from_millis(config.max_timeout).max(min);
+        Self {
+            adaptive: config.adaptive_timeout,
+            min,
+            max,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: Duration::from_millis(config.timeout),
+            backoff: 0,
+        }
+    }
+
+    // 当前等待超时时间，包含指数退避
+    pub fn timeout(&self) -> Duration {
+        if !self.adaptive {
+            return self.rto;
+        }
+        self.rto
+            .saturating_mul(1 << self.backoff)
+            .clamp(self.min, self.max)
+    }
+
+    // 平滑 RTT，非自适应模式下同样估计，只是不影响超时
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    // 记录一次有效的 RTT 采样（重传过的报文不应采样，Karn 算法）
+    pub fn on_sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = self.rttvar * 3 / 4 + delta / 4;
+                self.srtt = Some(srtt * 7 / 8 + rtt / 8);
+            }
+        }
+        if !self.adaptive {
+            return;
+        }
+        let srtt = self.srtt.unwrap();
+        self.rto = (srtt + (self.rttvar * 4).max(CLOCK_GRANULARITY)).clamp(self.min, self.max);
+        self.backoff = 0;
+    }
+
+    // 发生超时，下次等待时间加倍
+    pub fn on_timeout(&mut self) {
+        if self.adaptive && self.backoff < MAX_BACKOFF {
+            self.backoff += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn estimator(timeout: u64, min: u64, max: u64) -> RtoEstimator {
+        RtoEstimator::new(&TransferConfig {
+            timeout,
+            adaptive_timeout: true,
+            min_timeout: min,
+            max_timeout: max,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn first_sample_initializes_estimate() {
+        let mut rto = estimator(1000, 20, 10000);
+        assert_eq!(rto.timeout(), ms(1000));
+        assert_eq!(rto.srtt(), None);
+        // SRTT = R, RTTVAR = R/2, RTO = SRTT + 4 * RTTVAR
+        rto.on_sample(ms(100));
+        assert_eq!(rto.srtt(), Some(ms(100)));
+        assert_eq!(rto.rttvar, ms(50));
+        assert_eq!(rto.timeout(), ms(300));
+    }
+
+    #[test]
+    fn later_samples_are_smoothed() {
+        let mut rto = estimator(1000, 20, 10000);
+        rto.on_sample(ms(100));
+        // RTTVAR = 3/4 * 50 + 1/4 * |100 - 200|，SRTT = 7/8 * 100 + 1/8 * 200
+        rto.on_sample(ms(200));
+        assert_eq!(rto.rttvar, Duration::from_micros(62_500));
+        assert_eq!(rto.srtt(), Some(Duration::from_micros(112_500)));
+        assert_eq!(rto.timeout(), Duration::from_micros(362_500));
+    }
+
+    #[test]
+    fn timeouts_back_off_until_next_sample() {
+        let mut rto = estimator(1000, 20, 10000);
+        rto.on_sample(ms(100));
+        rto.on_timeout();
+        assert_eq!(rto.timeout(), ms(600));
+        rto.on_timeout();
+        assert_eq!(rto.timeout(), ms(1200));
+        // 退避后的超时同样受上限约束
+        for _ in 0..40 {
+            rto.on_timeout();
+        }
+        assert_eq!(rto.timeout(), ms(10000));
+        // 新的有效采样清除退避
+        rto.on_sample(ms(100));
+        assert!(rto.timeout() < ms(1000), "{:?}", rto.timeout());
+    }
+
+    #[test]
+    fn timeout_is_clamped() {
+        // RTO = 1 + max(4 * 0.5, G) = 3ms，低于下限
+        let mut rto = estimator(1000, 20, 10000);
+        rto.on_sample(ms(1));
+        assert_eq!(rto.timeout(), ms(20));
+
+        let mut rto = estimator(1000, 20, 10000);
+        rto.on_sample(ms(5000));
+        assert_eq!(rto.timeout(), ms(10000));
+
+        // 上限小于下限时以下限为准
+        let mut rto = estimator(1000, 500, 100);
+        rto.on_sample(ms(1));
+        assert_eq!(rto.timeout(), ms(500));
+    }
+
+    #[test]
+    fn fixed_timeout_ignores_samples() {
+        let mut rto = RtoEstimator::new(&TransferConfig {
+            timeout: 700,
+            ..Default::default()
+        });
+        rto.on_sample(ms(10));
+        rto.on_timeout();
+        assert_eq!(rto.timeout(), ms(700));
+        // 仍然记录平滑 RTT 用于统计
+        assert_eq!(rto.srtt(), Some(ms(10)));
+    }
+}
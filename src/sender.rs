@@ -0,0 +1,789 @@
+use crate::congestion::CongestionControl;
+use crate::rto::RtoEstimator;
+use crate::stats::TransferStats;
+use log::warn;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// 发送端下一步要执行的动作
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action<'a> {
+    // 读取下一块数据，通过 push_block 交给发送端
+    Read,
+    // 发送一个 DATA 块
+    Send { block: u16, data: &'a [u8] },
+    // 等待 ACK，最长等待 timeout()
+    Wait,
+    // 最后一块已被确认
+    Done,
+    // 传输失败，应通知对端
+    Fail(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum State {
+    Running,
+    Done,
+    Failed(String),
+}
+
+// 滑动窗口发送端状态机，不做任何 I/O：
+// 调用方反复 poll 执行动作，收到 ACK 或超时时分别调用 on_ack / on_timeout。
+// 块在内部按从 0 开始、不回绕的序号记录，只在收发时换算为 16 位块号
+pub struct Sender {
+    blksize: usize,
+    windowsize: u16,
+    retry: u8,
+    gbn: bool,
+    selective: bool,
+    rto: RtoEstimator,
+    cc: Box<dyn CongestionControl>,
+    state: State,
+    // 第一个未确认的块
+    base: u64,
+    // 已读取但未确认的块，blocks[0] 对应 base
+    blocks: VecDeque<Vec<u8>>,
+    // 下一个要发送的块
+    next: u64,
+    // 已发送过的块数（不含重传）
+    sent: u64,
+    // 最后一块（长度小于 blksize）
+    last: Option<u64>,
+    // 最近确认的块，接收端必定已收到
+    acked_tail: Option<(u64, Vec<u8>)>,
+    // 已确认的块的缓冲区，复用以避免每块分配
+    spare: Vec<Vec<u8>>,
+    // 待选择重传的块
+    retransmit: Option<u64>,
+    // 本轮是否发送过窗口内的块
+    burst: bool,
+    retries: u8,
+    // 用于 RTT 采样的块及其发送时间，只对首次发送的块采样
+    timing: Option<(u64, Instant)>,
+    // 最近一次选择重传的块、时间及还可忽略的重复 ACK 数：重传前已在途的块
+    // 各会再引起一次重复 ACK，超出的说明重传的块也丢了
+    resent: Option<(u64, Instant, u64)>,
+    dup_acks: u64,
+    // 统计：已确认的数据、重传的块、超时和重复 ACK 次数
+    bytes: u64,
+    retransmits: u64,
+    timeouts: u64,
+    duplicate_acks: u64,
+}
+
+impl Sender {
+    pub fn new(
+        blksize: u16,
+        windowsize: u16,
+        retry: u8,
+        rto: RtoEstimator,
+        cc: Box<dyn CongestionControl>,
+    ) -> Self {
+        Self {
+            blksize: usize::from(blksize),
+            windowsize: windowsize.max(1),
+            retry,
+            gbn: false,
+            selective: false,
+            rto,
+            cc,
+            state: State::Running,
+            base: 0,
+            blocks: VecDeque::new(),
+            next: 0,
+            sent: 0,
+            last: None,
+            acked_tail: None,
+            spare: Vec::new(),
+            retransmit: None,
+            burst: false,
+            retries: 0,
+            timing: None,
+            resent: None,
+            dup_acks: 0,
+            bytes: 0,
+            retransmits: 0,
+            timeouts: 0,
+            duplicate_acks: 0,
+        }
+    }
+
+    // GBN：接收端逐块 ACK，发送端不必等待窗口确认完
+    pub fn with_gbn(mut self, gbn: bool) -> Self {
+        self.gbn = gbn;
+        self
+    }
+
+    // 选择重传：只重传接收端缺少的块
+    pub fn with_selective(mut self, selective: bool) -> Self {
+        self.selective = selective;
+        self
+    }
+
+    // 每块的最大字节数
+    pub fn blksize(&self) -> usize {
+        self.blksize
+    }
+
+    // 当前等待 ACK 的超时时间
+    pub fn timeout(&self) -> Duration {
+        self.rto.timeout()
+    }
+
+    // 累加到传输的统计
+    pub fn add_stats(&self, stats: &mut TransferStats) {
+        stats.bytes += self.bytes;
+        stats.blocks += self.base;
+        stats.retransmits += self.retransmits;
+        stats.timeouts += self.timeouts;
+        stats.duplicate_acks += self.duplicate_acks;
+        stats.rtt = self.rto.srtt().or(stats.rtt);
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Action<'_> {
+        match &self.state {
+            State::Running => (),
+            State::Done => return Action::Done,
+            State::Failed(msg) => return Action::Fail(msg.clone()),
+        }
+        // 期间到达的 ACK 可能已确认了待重传的块
+        if let Some(index) = self.retransmit.take()
+            && index >= self.base
+        {
+            self.retransmits += 1;
+            return self.send(index);
+        }
+
+        let limit = u64::from(self.cc.window().clamp(1, self.windowsize));
+        let finished = self.last.is_some_and(|last| self.next > last);
+        if !finished && self.next < self.base + limit {
+            if self.next == self.base + self.blocks.len() as u64 {
+                return Action::Read;
+            }
+            let index = self.next;
+            self.next += 1;
+            if index == self.sent {
+                self.sent += 1;
+                if self.timing.is_none() {
+                    self.timing = Some((index, now));
+                }
+            } else {
+                self.retransmits += 1;
+            }
+            self.burst = true;
+            return self.send(index);
+        }
+
+        // 实际窗口小于协商窗口时接收端等不到窗口边界，重发一个已收到的块，
+        // 接收端收到重复块会立即 ACK；尚无确认过的块时重发最后一块
+        if std::mem::take(&mut self.burst)
+            && !self.gbn
+            && !finished
+            && self.cc.window() < self.windowsize
+        {
+            self.retransmits += 1;
+            return match &self.acked_tail {
+                Some((index, data)) => Action::Send {
+                    block: block_of(*index),
+                    data,
+                },
+                None => self.send(self.next - 1),
+            };
+        }
+        Action::Wait
+    }
+
+    // 提供 Read 请求的数据，长度小于 blksize 的块为最后一块
+    pub fn push_block(&mut self, data: &[u8]) {
+        let index = self.base + self.blocks.len() as u64;
+        if data.len() < self.blksize {
+            self.last = Some(index);
+        }
+        let mut buf = self.spare.pop().unwrap_or_default();
+        buf.clear();
+        buf.extend_from_slice(data);
+        self.blocks.push_back(buf);
+    }
+
+    pub fn on_ack(&mut self, ack: u16, now: Instant) {
+        if self.state != State::Running {
+            return;
+        }
+        self.retries = 0;
+        let offset = ack.wrapping_sub(block_of(self.base));
+        let acked = self.base + u64::from(offset);
+        if offset < self.windowsize {
+            // 确认了从未发送过的块，忽略
+            if acked >= self.sent {
+                return;
+            }
+            if let Some((index, sent_at)) = self.timing
+                && acked >= index
+            {
+                self.rto.on_sample(now.saturating_duration_since(sent_at));
+                self.timing = None;
+            }
+            if self.last == Some(acked) {
+                // 最后一块之前的块必定都已收到
+                self.bytes += self.blocks.iter().map(|b| b.len() as u64).sum::<u64>();
+                self.blocks.clear();
+                self.base = acked + 1;
+                self.state = State::Done;
+                return;
+            }
+            self.cc.on_ack(offset + 1);
+            while self.base <= acked {
+                let tail = self.blocks.pop_front().map(|data| (self.base, data));
+                if let Some((_, data)) = &tail {
+                    self.bytes += data.len() as u64;
+                }
+                if let Some((_, buf)) = std::mem::replace(&mut self.acked_tail, tail) {
+                    self.spare.push(buf);
+                }
+                self.base += 1;
+            }
+            self.dup_acks = 0;
+            // GBN 回退后迟到的 ACK 可能越过 next
+            self.next = self.next.max(self.base);
+            if self.selective {
+                self.selective_resend(false, now);
+            } else if !self.gbn && self.next > self.base {
+                // 接收端只确认了部分已发送的块，说明中间有丢包
+                self.go_back();
+                self.cc.on_loss();
+            }
+            return;
+        }
+
+        self.duplicate_acks += 1;
+        if self.selective && offset == u16::MAX {
+            self.dup_acks += 1;
+            self.selective_resend(true, now);
+            return;
+        }
+        // 收到已经确认过的 ACK，说明有丢包，重传窗口
+        if self.next != self.base {
+            self.go_back();
+            self.cc.on_loss();
+        }
+    }
+
+    pub fn on_timeout(&mut self) {
+        if self.state != State::Running {
+            return;
+        }
+        warn!("timeout");
+        self.timeouts += 1;
+        self.rto.on_timeout();
+        self.cc.on_timeout();
+        self.timing = None;
+        self.resent = None;
+        self.retries += 1;
+        if self.retries >= self.retry {
+            self.state = State::Failed("Max retries reached".to_string());
+            return;
+        }
+        if self.next != self.base {
+            self.go_back();
+        }
+    }
+
+    fn send(&self, index: u64) -> Action<'_> {
+        Action::Send {
+            block: block_of(index),
+            data: &self.blocks[(index - self.base) as usize],
+        }
+    }
+
+    fn go_back(&mut self) {
+        warn!("retrans #{}", block_of(self.base));
+        self.next = self.base;
+        self.timing = None;
+    }
+
+    // 重复 ACK 表示接收端缺少 base；非 GBN 模式下接收端只在窗口边界或出现缺口时 ACK，
+    // 未确认完已发送块的 ACK 同样表示缺口
+    fn selective_resend(&mut self, duplicate: bool, now: Instant) {
+        let timeout = self.rto.timeout();
+        let suppressed = match &mut self.resent {
+            Some((index, sent_at, pending))
+                if *index == self.base
+                    && *pending > 0
+                    && now.saturating_duration_since(*sent_at) < timeout =>
+            {
+                *pending -= 1;
+                true
+            }
+            _ => false,
+        };
+        if self.next != self.base && (duplicate || !self.gbn) && !suppressed {
+            warn!("selective retrans #{}", block_of(self.base));
+            self.retransmit = Some(self.base);
+            self.cc.on_loss();
+            let in_flight = (self.sent - self.base - 1).saturating_sub(self.dup_acks);
+            self.resent = Some((self.base, now, in_flight));
+        }
+    }
+}
+
+// 块号从 1 开始，超过 65535 后回绕到 0
+fn block_of(index: u64) -> u16 {
+    (index + 1) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::congestion::Congestion;
+    use crate::proto::TransferConfig;
+    use std::collections::HashMap;
+
+    const BLKSIZE: u16 = 4;
+
+    fn sender(windowsize: u16, retry: u8) -> Sender {
+        let config = TransferConfig {
+            timeout: 100,
+            ..Default::default()
+        };
+        let cc = Congestion::Fixed.build(windowsize);
+        Sender::new(BLKSIZE, windowsize, retry, RtoEstimator::new(&config), cc)
+    }
+
+    // 按块提供数据，模拟顺序读文件
+    struct Source {
+        data: Vec<u8>,
+        pos: usize,
+        blksize: usize,
+    }
+
+    impl Source {
+        fn new(data: Vec<u8>, blksize: u16) -> Self {
+            Self {
+                data,
+                pos: 0,
+                blksize: usize::from(blksize),
+            }
+        }
+
+        fn read(&mut self) -> Vec<u8> {
+            let end = (self.pos + self.blksize).min(self.data.len());
+            let block = self.data[self.pos..end].to_vec();
+            self.pos = end;
+            block
+        }
+    }
+
+    fn bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    // 执行动作直到需要等待，返回期间发出的块
+    fn flush(sender: &mut Sender, source: &mut Source) -> Vec<(u16, Vec<u8>)> {
+        let now = Instant::now();
+        let mut sent = Vec::new();
+        loop {
+            match sender.poll(now) {
+                Action::Read => sender.push_block(&source.read()),
+                Action::Send { block, data } => sent.push((block, data.to_vec())),
+                _ => return sent,
+            }
+        }
+    }
+
+    fn blocks(sent: &[(u16, Vec<u8>)]) -> Vec<u16> {
+        sent.iter().map(|(block, _)| *block).collect()
+    }
+
+    #[test]
+    fn sends_window_and_slides_on_ack() {
+        let mut source = Source::new(bytes(38), BLKSIZE);
+        let mut sender = sender(4, 3);
+        let now = Instant::now();
+
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [1, 2, 3, 4]);
+        assert_eq!(sender.poll(now), Action::Wait);
+        sender.on_ack(4, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [5, 6, 7, 8]);
+        sender.on_ack(8, now);
+        let sent = flush(&mut sender, &mut source);
+        assert_eq!(blocks(&sent), [9, 10]);
+        assert_eq!(sent[1].1, bytes(38)[36..]);
+        sender.on_ack(10, now);
+        assert_eq!(sender.poll(now), Action::Done);
+    }
+
+    #[test]
+    fn empty_final_block_when_size_is_multiple_of_blksize() {
+        let mut source = Source::new(bytes(8), BLKSIZE);
+        let mut sender = sender(4, 3);
+        let now = Instant::now();
+
+        let sent = flush(&mut sender, &mut source);
+        assert_eq!(blocks(&sent), [1, 2, 3]);
+        assert!(sent[2].1.is_empty());
+        // 部分确认，重传空的最后一块
+        sender.on_ack(2, now);
+        assert_eq!(flush(&mut sender, &mut source), [(3, Vec::new())]);
+        sender.on_ack(3, now);
+        assert_eq!(sender.poll(now), Action::Done);
+    }
+
+    #[test]
+    fn final_short_block_is_retransmitted_on_timeout() {
+        let data = bytes(10);
+        let mut source = Source::new(data.clone(), BLKSIZE);
+        let mut sender = sender(4, 3);
+
+        let first = flush(&mut sender, &mut source);
+        assert_eq!(blocks(&first), [1, 2, 3]);
+        sender.on_timeout();
+        assert_eq!(flush(&mut sender, &mut source), first);
+
+        // 只丢了最后一块，重传的仍是同样的短块
+        sender.on_ack(2, Instant::now());
+        let sent = flush(&mut sender, &mut source);
+        assert_eq!(sent, [(3, data[8..].to_vec())]);
+        sender.on_ack(3, Instant::now());
+        assert_eq!(sender.poll(Instant::now()), Action::Done);
+    }
+
+    #[test]
+    fn duplicate_ack_goes_back_to_window_start() {
+        let mut source = Source::new(bytes(40), BLKSIZE);
+        let mut sender = sender(4, 3);
+        let now = Instant::now();
+
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [1, 2, 3, 4]);
+        sender.on_ack(0, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [1, 2, 3, 4]);
+        // 部分确认说明后面的块丢了
+        sender.on_ack(2, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [3, 4, 5, 6]);
+        // 重复的旧 ACK
+        sender.on_ack(2, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn ack_for_unsent_block_is_ignored() {
+        let mut source = Source::new(bytes(40), BLKSIZE);
+        let mut sender = sender(8, 3);
+        let now = Instant::now();
+
+        sender.push_block(&source.read());
+        sender.push_block(&source.read());
+        assert_eq!(
+            sender.poll(now),
+            Action::Send {
+                block: 1,
+                data: &bytes(40)[..4]
+            }
+        );
+        sender.on_ack(5, now);
+        assert_eq!(
+            blocks(&flush(&mut sender, &mut source)),
+            [2, 3, 4, 5, 6, 7, 8]
+        );
+    }
+
+    #[test]
+    fn gbn_keeps_sending_on_partial_ack() {
+        let mut source = Source::new(bytes(40), BLKSIZE);
+        let mut sender = sender(4, 3).with_gbn(true);
+        let now = Instant::now();
+
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [1, 2, 3, 4]);
+        sender.on_ack(1, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [5]);
+        sender.on_ack(1, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [2, 3, 4, 5]);
+        // 回退后迟到的 ACK 越过了下一个要发送的块
+        sender.on_ack(5, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn selective_resends_only_missing_block() {
+        let mut source = Source::new(bytes(40), BLKSIZE);
+        let mut sender = sender(4, 3).with_selective(true);
+        let now = Instant::now();
+
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [1, 2, 3, 4]);
+        sender.on_ack(0, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [1]);
+        // 块 3、4 引起的重复 ACK 在预期之内
+        sender.on_ack(0, now);
+        sender.on_ack(0, now);
+        assert!(flush(&mut sender, &mut source).is_empty());
+        // 超出预期的重复 ACK 说明重传的块也丢了
+        sender.on_ack(0, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [1]);
+        sender.on_ack(4, now);
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn fails_after_max_retries() {
+        let mut source = Source::new(bytes(40), BLKSIZE);
+        let mut sender = sender(4, 3);
+        let now = Instant::now();
+
+        flush(&mut sender, &mut source);
+        sender.on_timeout();
+        sender.on_timeout();
+        assert_eq!(blocks(&flush(&mut sender, &mut source)), [1, 2, 3, 4]);
+        sender.on_timeout();
+        assert_eq!(
+            sender.poll(now),
+            Action::Fail("Max retries reached".to_string())
+        );
+    }
+
+    // 统计只计入已确认的块，读取后尚未确认的不算
+    #[test]
+    fn stats_count_acknowledged_data() {
+        let mut source = Source::new(bytes(38), BLKSIZE);
+        let mut sender = sender(4, 3);
+        let now = Instant::now();
+        let stats = |sender: &Sender| {
+            let mut stats = TransferStats::default();
+            sender.add_stats(&mut stats);
+            (stats.bytes, stats.blocks)
+        };
+
+        flush(&mut sender, &mut source);
+        assert_eq!(stats(&sender), (0, 0));
+        sender.on_ack(2, now);
+        assert_eq!(stats(&sender), (8, 2));
+        flush(&mut sender, &mut source);
+        sender.on_ack(6, now);
+        assert_eq!(stats(&sender), (24, 6));
+        flush(&mut sender, &mut source);
+        // 最后一块确认时计入窗口内剩余的块
+        sender.on_ack(10, now);
+        assert_eq!(sender.poll(now), Action::Done);
+        assert_eq!(stats(&sender), (38, 10));
+    }
+
+    // retry 为 0 时第一次超时即失败，计数不会一直增加到溢出
+    #[test]
+    fn zero_retry_fails_on_first_timeout() {
+        let mut source = Source::new(bytes(40), BLKSIZE);
+        let mut sender = sender(4, 0);
+        let now = Instant::now();
+
+        flush(&mut sender, &mut source);
+        sender.on_timeout();
+        assert_eq!(
+            sender.poll(now),
+            Action::Fail("Max retries reached".to_string())
+        );
+        for _ in 0..300 {
+            sender.on_timeout();
+        }
+        assert_eq!(
+            sender.poll(now),
+            Action::Fail("Max retries reached".to_string())
+        );
+    }
+
+    #[test]
+    fn block_numbers_wrap_around() {
+        let len = 70000;
+        let expected = bytes(len);
+        let mut source = Source::new(expected.clone(), 1);
+        let config = TransferConfig::default();
+        let cc = Congestion::Fixed.build(16);
+        let mut sender = Sender::new(1, 16, 3, RtoEstimator::new(&config), cc);
+        let now = Instant::now();
+
+        let mut index = 0usize;
+        loop {
+            let sent = flush(&mut sender, &mut source);
+            for (block, data) in &sent {
+                assert_eq!(*block, (index + 1) as u16);
+                assert_eq!(data.as_slice(), &expected[index..(index + 1).min(len)]);
+                index += 1;
+            }
+            sender.on_ack(sent.last().unwrap().0, now);
+            if sender.poll(now) == Action::Done {
+                break;
+            }
+        }
+        // 最后一块是空块
+        assert_eq!(index, len + 1);
+    }
+
+    // 模拟 RFC 7440 接收端，选择重传时缓存乱序到达的块
+    struct Receiver {
+        windowsize: u16,
+        selective: bool,
+        expected: u16,
+        count: u16,
+        buffered: HashMap<u16, Vec<u8>>,
+        data: Vec<u8>,
+        done: bool,
+    }
+
+    impl Receiver {
+        fn new(windowsize: u16, selective: bool) -> Self {
+            Self {
+                windowsize,
+                selective,
+                expected: 1,
+                count: 0,
+                buffered: HashMap::new(),
+                data: Vec::new(),
+                done: false,
+            }
+        }
+
+        fn on_data(&mut self, mut block: u16, mut data: Vec<u8>) -> Option<u16> {
+            if self.done {
+                return Some(self.expected);
+            }
+            if block != self.expected {
+                if self.selective && block.wrapping_sub(self.expected) < self.windowsize * 2 {
+                    self.buffered.insert(block, data);
+                }
+                self.count = 0;
+                return Some(self.expected.wrapping_sub(1));
+            }
+            loop {
+                self.data.extend_from_slice(&data);
+                self.count += 1;
+                if data.len() < usize::from(BLKSIZE) {
+                    self.done = true;
+                    self.expected = block;
+                    return Some(block);
+                }
+                self.expected = self.expected.wrapping_add(1);
+                match self.buffered.remove(&self.expected) {
+                    Some(next) => {
+                        block = self.expected;
+                        data = next;
+                    }
+                    None => break,
+                }
+            }
+            if self.count >= self.windowsize || !self.buffered.is_empty() {
+                self.count = 0;
+                return Some(self.expected.wrapping_sub(1));
+            }
+            None
+        }
+
+        fn on_timeout(&mut self) -> u16 {
+            self.count = 0;
+            self.expected.wrapping_sub(u16::from(!self.done))
+        }
+    }
+
+    // 固定种子的伪随机丢包
+    struct Loss {
+        state: u64,
+        percent: u64,
+    }
+
+    impl Loss {
+        fn drop(&mut self) -> bool {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            self.state % 100 < self.percent
+        }
+    }
+
+    fn simulate(sender: &mut Sender, data: &[u8], selective: bool, windowsize: u16, seed: u64) {
+        let mut source = Source::new(data.to_vec(), BLKSIZE);
+        let mut receiver = Receiver::new(windowsize, selective);
+        let mut loss = Loss {
+            state: seed,
+            percent: 10,
+        };
+        let mut acks = VecDeque::new();
+        let mut now = Instant::now();
+        for _ in 0..1_000_000 {
+            now += Duration::from_micros(10);
+            // 与异步驱动一致，优先处理已到达的 ACK
+            if let Some(ack) = acks.pop_front() {
+                sender.on_ack(ack, now);
+                continue;
+            }
+            match sender.poll(now) {
+                Action::Read => sender.push_block(&source.read()),
+                Action::Send { block, data } => {
+                    if !loss.drop()
+                        && let Some(ack) = receiver.on_data(block, data.to_vec())
+                        && !loss.drop()
+                    {
+                        acks.push_back(ack);
+                    }
+                }
+                Action::Wait => {
+                    // 接收端先超时重发 ACK，这个 ACK 也丢了才轮到发送端超时
+                    now += sender.timeout();
+                    let ack = receiver.on_timeout();
+                    if loss.drop() {
+                        sender.on_timeout();
+                    } else {
+                        acks.push_back(ack);
+                    }
+                }
+                Action::Done => {
+                    assert!(receiver.done);
+                    assert_eq!(receiver.data, data);
+                    return;
+                }
+                Action::Fail(msg) => panic!("transfer failed: {msg}"),
+            }
+        }
+        panic!("transfer did not finish");
+    }
+
+    #[test]
+    fn lossy_transfers_deliver_all_data() {
+        let windows = [1, 2, 3, 4, 8, 16];
+        for windowsize in windows {
+            let w = usize::from(windowsize) * usize::from(BLKSIZE);
+            let sizes = [0, 1, 3, 4, 5, w - 1, w, w + 1, 3 * w, 97, 400];
+            for len in sizes {
+                for congestion in [Congestion::Fixed, Congestion::Aimd] {
+                    for (gbn, selective) in [(false, false), (true, false), (false, true)] {
+                        for seed in 1..=4 {
+                            let config = TransferConfig::default();
+                            let mut sender = Sender::new(
+                                BLKSIZE,
+                                windowsize,
+                                u8::MAX,
+                                RtoEstimator::new(&config),
+                                congestion.build(windowsize),
+                            )
+                            .with_gbn(gbn)
+                            .with_selective(selective);
+                            let data = bytes(len);
+                            simulate(&mut sender, &data, selective, windowsize, seed);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lossy_transfer_across_wraparound() {
+        let data = bytes(usize::from(BLKSIZE) * 70000 + 1);
+        for selective in [false, true] {
+            let config = TransferConfig::default();
+            let mut sender = Sender::new(
+                BLKSIZE,
+                16,
+                u8::MAX,
+                RtoEstimator::new(&config),
+                Congestion::Fixed.build(16),
+            )
+            .with_selective(selective);
+            simulate(&mut sender, &data, selective, 16, 7);
+        }
+    }
+}
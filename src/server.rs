@@ -1,77 +1,417 @@
 use crate::SessionConfig;
-use crate::packet::TftpPacket;
+use crate::content::{ContentProvider, UploadSink};
+use crate::event::{Emitter, EventKind, EventSender, TransferEvent};
+use crate::handler::{OptionHandler, OptionHandlers};
+use crate::packet::{ERR_NOT_DEFINED, TftpPacket, TftpPacketRef};
+use crate::port::bind_socket;
+use crate::ratelimit::RateLimiter;
 use crate::session::Session;
-use log::{error, info};
+use crate::transport::Transport;
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use tokio::net::UdpSocket;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{Duration, Instant, sleep};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+const DEF_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_PACKET_SIZE: usize = 65536;
+// 单端口模式下每个会话缓存的待处理报文数
+const SESSION_QUEUE_SIZE: usize = 256;
+// subscribe 的每个订阅者缓存的事件数，处理不及时的订阅者会丢失较早的事件
+const EVENT_QUEUE_SIZE: usize = 1024;
 
 pub struct TftpServer {
     addr: SocketAddr,
     config: SessionConfig,
+    limiter: Option<Arc<RateLimiter>>,
+    shutdown: CancellationToken,
+    drain_timeout: Duration,
+    single_port: bool,
+    handlers: OptionHandlers,
+    provider: Option<Arc<dyn ContentProvider>>,
+    sink: Option<Arc<dyn UploadSink>>,
+    events: EventSender,
 }
 
 impl TftpServer {
     pub fn new(addr: SocketAddr, config: SessionConfig) -> Self {
-        Self { addr, config }
+        Self {
+            addr,
+            config,
+            limiter: None,
+            shutdown: CancellationToken::new(),
+            drain_timeout: DEF_DRAIN_TIMEOUT,
+            single_port: false,
+            handlers: OptionHandlers::default(),
+            provider: None,
+            sink: None,
+            events: EventSender::new(EVENT_QUEUE_SIZE),
+        }
+    }
+
+    // 所有会话共享的发送速率上限（字节/秒）
+    pub fn with_rate_limit(mut self, rate: u64) -> Self {
+        self.limiter = Some(Arc::new(RateLimiter::new(rate)));
+        self
+    }
+
+    // 停止服务后等待进行中传输完成的最长时间，超时则中止剩余会话
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    // 取消该 token 即触发停机：不再接受新请求，等待进行中的传输结束
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    // 单端口模式：所有会话复用监听端口，按对端地址分发报文，便于穿越 NAT
+    pub fn with_single_port(mut self, single_port: bool) -> Self {
+        self.single_port = single_port;
+        self
+    }
+
+    // 为自定义选项注册处理函数（名称不区分大小写），未注册的未知选项仍被忽略
+    pub fn with_option_handler(
+        mut self,
+        name: &str,
+        handler: impl OptionHandler + 'static,
+    ) -> Self {
+        self.handlers.register(name, Arc::new(handler));
+        self
+    }
+
+    // RRQ 先交给内容提供者，提供者不处理时再读取服务目录下的文件
+    pub fn with_content_provider(mut self, provider: impl ContentProvider + 'static) -> Self {
+        self.provider = Some(Arc::new(provider));
+        self
+    }
+
+    // WRQ 先交给上传接收者，接收者不处理时再写入服务目录
+    pub fn with_upload_sink(mut self, sink: impl UploadSink + 'static) -> Self {
+        self.sink = Some(Arc::new(sink));
+        self
+    }
+
+    // 订阅所有传输的生命周期事件
+    pub fn subscribe(&self) -> broadcast::Receiver<TransferEvent> {
+        self.events.subscribe()
+    }
+
+    // 只订阅传输结束的 Completed 和 Failed 事件，通道不限长度，不会丢失事件
+    pub fn subscribe_outcomes(&self) -> mpsc::UnboundedReceiver<TransferEvent> {
+        self.events.subscribe_outcomes()
     }
 
     pub async fn run(&self) -> anyhow::Result<()> {
         let socket = UdpSocket::bind(self.addr).await?;
+        self.run_with_socket(socket).await
+    }
+
+    // 在已绑定的监听套接字上运行，忽略 new() 中的地址
+    pub async fn run_with_socket(&self, socket: UdpSocket) -> anyhow::Result<()> {
+        let addr = socket.local_addr()?;
+        let socket = Arc::new(socket);
+        let tracker = TaskTracker::new();
+        let abort = CancellationToken::new();
+        let router = Router::default();
+        let active = ActiveRequests::default();
+        let deadline = sleep(Duration::MAX);
+        tokio::pin!(deadline);
+        let mut draining = false;
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
 
-        info!("TFTP server listening on {}", self.addr);
+        info!("TFTP server listening on {addr}");
 
         loop {
-            let mut buf = [0u8; 1500];
-            let (len, peer) = socket.recv_from(&mut buf).await?;
-
-            if let Ok(pkt) = TftpPacket::deserialize(&buf[..len]) {
-                info!("{peer} {pkt:?}");
-                match pkt {
-                    TftpPacket::RRQ {
-                        filename,
-                        mode,
-                        options,
-                    } => {
-                        let config = self.config.clone();
-                        tokio::spawn(async move {
-                            let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-                            socket.connect(peer).await.unwrap();
-                            let mut session = Session::new(socket, config);
-                            if let Err(e) = session.negotiation(filename, mode, options).await {
-                                error!("{peer} RRQ negotiation failed: {e}");
-                                return;
-                            }
-                            if let Err(e) = session.send_file().await {
-                                error!("{peer} send_file failed: {e}");
-                            }
-                        });
+            let (len, peer) = tokio::select! {
+                res = socket.recv_from(&mut buf) => match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        // 如 ICMP 端口不可达导致的 ECONNREFUSED，属于瞬时错误
+                        warn!("recv_from failed: {e}");
+                        continue;
                     }
-                    TftpPacket::WRQ {
-                        filename,
-                        mode,
-                        options,
-                    } => {
-                        let config = self.config.clone();
-                        tokio::spawn(async move {
-                            let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-                            socket.connect(peer).await.unwrap();
-                            let mut session = Session::new(socket, config);
-                            if let Err(e) = session
-                                .negotiation_wrq(filename, mode, options)
-                                .await
-                                .and_then(|_| Ok(()))
-                            {
-                                error!("{peer} WRQ negotiation failed: {e}");
-                                return;
-                            }
-                            if let Err(e) = session.recv_file().await {
-                                error!("{peer} recv_file failed: {e}");
-                            }
-                        });
+                },
+                _ = self.shutdown.cancelled(), if !draining => {
+                    draining = true;
+                    tracker.close();
+                    info!(
+                        "shutting down, draining {} active sessions (deadline {:?})",
+                        tracker.len(),
+                        self.drain_timeout
+                    );
+                    deadline.as_mut().reset(Instant::now() + self.drain_timeout);
+                    continue;
+                }
+                _ = tracker.wait(), if draining => break,
+                _ = &mut deadline, if draining => {
+                    warn!("drain deadline reached, aborting {} sessions", tracker.len());
+                    abort.cancel();
+                    tracker.wait().await;
+                    break;
+                }
+            };
+
+            // 只借用解析以区分请求，会话报文原样转发
+            let Ok(pkt) = TftpPacketRef::deserialize(&buf[..len]) else {
+                continue;
+            };
+            let is_request = matches!(pkt, TftpPacketRef::RRQ { .. } | TftpPacketRef::WRQ { .. });
+
+            if self.single_port
+                && let Some(tx) = router.get(&peer)
+            {
+                // 同一对端的重复请求直接丢弃，其余报文交给会话处理
+                if !is_request {
+                    match tx.try_send(buf[..len].to_vec()) {
+                        Ok(()) => (),
+                        Err(TrySendError::Full(_)) => {
+                            debug!("{peer} session queue full, packet dropped");
+                        }
+                        Err(TrySendError::Closed(_)) => {
+                            debug!("{peer} session finished, packet dropped");
+                        }
                     }
-                    _ => (),
                 }
+                continue;
+            }
+            if !is_request {
+                continue;
             }
+            let pkt = pkt.into_owned();
+
+            info!("{peer} {pkt:?}");
+            if draining {
+                let pkt = TftpPacket::ERROR {
+                    code: ERR_NOT_DEFINED,
+                    msg: "Server shutting down".to_string(),
+                };
+                if let Err(e) = socket.send_to(&pkt.serialize(), peer).await {
+                    warn!("{peer} failed to reject request: {e}");
+                }
+                continue;
+            }
+            // 客户端超时重发的请求，原会话仍在处理，不再重复创建
+            let Some(request_guard) = active.insert(peer, &pkt) else {
+                info!("{peer} duplicate request ignored");
+                continue;
+            };
+            let route = self.single_port.then(|| router.register(peer));
+            let session = handle_request(
+                socket.clone(),
+                peer,
+                pkt,
+                route,
+                request_guard,
+                SessionSetup {
+                    config: self.config.clone(),
+                    limiter: self.limiter.clone(),
+                    handlers: self.handlers.clone(),
+                    provider: self.provider.clone(),
+                    sink: self.sink.clone(),
+                    events: self.events.clone(),
+                },
+                abort.clone(),
+            );
+            tracker.spawn(session);
         }
+
+        info!("TFTP server stopped");
+        Ok(())
+    }
+}
+
+// 创建会话所需的服务端设置
+struct SessionSetup {
+    config: SessionConfig,
+    limiter: Option<Arc<RateLimiter>>,
+    handlers: OptionHandlers,
+    provider: Option<Arc<dyn ContentProvider>>,
+    sink: Option<Arc<dyn UploadSink>>,
+    events: EventSender,
+}
+
+async fn handle_request(
+    listener: Arc<UdpSocket>,
+    peer: SocketAddr,
+    request: TftpPacket,
+    route: Option<Route>,
+    request_guard: RequestGuard,
+    setup: SessionSetup,
+    abort: CancellationToken,
+) {
+    let mut session = match open_session(&listener, peer, route, setup.config).await {
+        Ok(session) => session,
+        Err(e) => {
+            error!("{peer} session setup failed: {e}");
+            let msg = format!("Session setup failed: {e}");
+            // 会话没有建立，由这里发出事件
+            if let TftpPacket::RRQ { filename, mode, .. } | TftpPacket::WRQ { filename, mode, .. } =
+                &request
+            {
+                let write = matches!(request, TftpPacket::WRQ { .. });
+                let emitter =
+                    Emitter::new(setup.events, peer, filename.clone(), mode.clone(), write);
+                emitter.emit(EventKind::RequestReceived);
+                emitter.emit(EventKind::Failed {
+                    code: ERR_NOT_DEFINED,
+                    reason: msg.clone(),
+                });
+            }
+            // 无法创建传输端口，借用监听端口回复错误
+            let pkt = TftpPacket::ERROR {
+                code: ERR_NOT_DEFINED,
+                msg,
+            };
+            if let Err(e) = listener.send_to(&pkt.serialize(), peer).await {
+                warn!("{peer} failed to report setup error: {e}");
+            }
+            return;
+        }
+    };
+    session.set_shared_limiter(setup.limiter);
+    session.set_option_handlers(setup.handlers);
+    session.set_content_provider(setup.provider);
+    session.set_upload_sink(setup.sink);
+    session.set_events(setup.events);
+    session.set_request_guard(request_guard);
+
+    let transfer = async {
+        if let Err(e) = session.serve(request).await {
+            error!("{peer} transfer failed: {e}");
+        }
+    };
+    let aborted = tokio::select! {
+        _ = transfer => false,
+        _ = abort.cancelled() => true,
+    };
+    if aborted {
+        // 通知对端会话因停机被中止
+        if let Err(e) = session
+            .abort(ERR_NOT_DEFINED, "Server shutting down".to_string())
+            .await
+        {
+            warn!("{peer} aborted: {e}");
+        }
+    }
+}
+
+// 为会话分配独立的传输端口（TID），单端口模式下复用监听端口
+async fn open_session(
+    listener: &Arc<UdpSocket>,
+    peer: SocketAddr,
+    route: Option<Route>,
+    config: SessionConfig,
+) -> io::Result<Session> {
+    let transport = match route {
+        Some(route) => Transport::Shared {
+            socket: listener.clone(),
+            peer,
+            rx: route.rx,
+            _route: route.guard,
+        },
+        None => {
+            let socket = bind_socket(peer, config.port_range).await?;
+            socket.connect(peer).await?;
+            Transport::Socket(socket)
+        }
+    };
+    Ok(Session::new(transport, config))
+}
+
+// 正在处理的请求，按 (对端地址, 操作码, 文件名) 去重
+#[derive(Clone, Default)]
+struct ActiveRequests {
+    requests: Arc<Mutex<HashSet<RequestKey>>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct RequestKey {
+    peer: SocketAddr,
+    opcode: u16,
+    filename: String,
+}
+
+impl ActiveRequests {
+    // 请求已在处理中时返回 None
+    fn insert(&self, peer: SocketAddr, request: &TftpPacket) -> Option<RequestGuard> {
+        let (opcode, filename) = match request {
+            TftpPacket::RRQ { filename, .. } => (1, filename),
+            TftpPacket::WRQ { filename, .. } => (2, filename),
+            _ => return None,
+        };
+        let key = RequestKey {
+            peer,
+            opcode,
+            filename: filename.clone(),
+        };
+        if !self.requests.lock().unwrap().insert(key.clone()) {
+            return None;
+        }
+        Some(RequestGuard {
+            active: self.clone(),
+            key,
+        })
+    }
+}
+
+// 释放时移除请求记录
+pub(crate) struct RequestGuard {
+    active: ActiveRequests,
+    key: RequestKey,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.active.requests.lock().unwrap().remove(&self.key);
+    }
+}
+
+// 单端口模式下对端地址到会话的路由表
+#[derive(Clone, Default)]
+struct Router {
+    routes: Arc<Mutex<HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>>,
+}
+
+impl Router {
+    fn get(&self, peer: &SocketAddr) -> Option<mpsc::Sender<Vec<u8>>> {
+        self.routes.lock().unwrap().get(peer).cloned()
+    }
+
+    fn register(&self, peer: SocketAddr) -> Route {
+        let (tx, rx) = mpsc::channel(SESSION_QUEUE_SIZE);
+        self.routes.lock().unwrap().insert(peer, tx);
+        Route {
+            rx,
+            guard: RouteGuard {
+                router: self.clone(),
+                peer,
+            },
+        }
+    }
+}
+
+struct Route {
+    rx: mpsc::Receiver<Vec<u8>>,
+    guard: RouteGuard,
+}
+
+// 会话结束时注销路由
+pub struct RouteGuard {
+    router: Router,
+    peer: SocketAddr,
+}
+
+impl Drop for RouteGuard {
+    fn drop(&mut self) {
+        self.router.routes.lock().unwrap().remove(&self.peer);
     }
 }
@@ -1,19 +1,35 @@
-use crate::packet::TftpPacket;
-use crate::window::Window;
+use crate::congestion::Congestion;
+use crate::content::{ContentProvider, Upload, UploadSink};
+use crate::event::{Emitter, EventKind, EventSender};
+use crate::handler::{OptionHandlers, RequestContext};
+use crate::options::TftpOptions;
+use crate::packet::{
+    ERR_ACCESS_VIOLATION, ERR_DISK_FULL, ERR_FILE_EXISTS, ERR_FILE_NOT_FOUND, ERR_NOT_DEFINED,
+    ERR_OPTION, TftpPacket,
+};
+use crate::port::PortRange;
+use crate::proto::{ClientTransfer, Output, ServerTransfer, Transfer, TransferConfig};
+use crate::ratelimit::RateLimiter;
+use crate::server::RequestGuard;
+use crate::stats::{Progress, TransferStats};
+use crate::transport::Transport;
 use anyhow::anyhow;
 use log::{info, warn};
-use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{Read, Write, Seek, SeekFrom};
+use std::io::{self, Read, Write};
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use tokio::net::UdpSocket;
+use std::sync::Arc;
+use std::{error, fmt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
 use tokio::time::{Duration, Instant, timeout};
+use tokio_util::sync::CancellationToken;
 
-const DEF_BLOCK_SIZE: u16 = 512; // RFC 1350
-const MIN_BLOCK_SIZE: u16 = 8; // RFC 2348
-const MAX_BLOCK_SIZE: u16 = 65464; // RFC 2348
-const DEF_WINDOW_SIZE: u16 = 1;
+// 能收到的最大报文
+const MAX_DATAGRAM: usize = 65536;
+// 发送 Progress 事件、更新进度的最小间隔
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
 
 #[derive(Clone, Debug)]
 pub struct SessionConfig {
@@ -21,31 +37,150 @@ pub struct SessionConfig {
     pub timeout: u64,
     pub retry: u8,
     pub gbn: bool,
+    // 单个会话发送速率上限（字节/秒），None 表示不限速
+    pub rate_limit: Option<u64>,
+    // 传输端口范围，None 表示由系统分配临时端口
+    pub port_range: Option<PortRange>,
+    // 根据 RTT 自适应调整超时（RFC 6298），timeout 作为初始值
+    pub adaptive_timeout: bool,
+    // 自适应超时的上下限（ms）
+    pub min_timeout: u64,
+    pub max_timeout: u64,
+    // 选择重传：缓存乱序到达的块，发送端只重传缺失的块（需对端支持）
+    pub selective: bool,
+    // 发送窗口的拥塞控制算法
+    pub congestion: Congestion,
+    // 一个窗口最多缓存的字节数，服务端据此缩小对端请求的 windowsize
+    pub window_budget: u64,
 }
 
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("."),
+            timeout: 1000,
+            retry: 3,
+            gbn: false,
+            rate_limit: None,
+            port_range: None,
+            adaptive_timeout: false,
+            min_timeout: 20,
+            max_timeout: 10000,
+            selective: false,
+            congestion: Congestion::default(),
+            window_budget: TransferConfig::default().window_budget,
+        }
+    }
+}
+
+impl SessionConfig {
+    // 协议核心使用的传输参数
+    pub fn transfer_config(&self) -> TransferConfig {
+        TransferConfig {
+            timeout: self.timeout,
+            retry: self.retry,
+            gbn: self.gbn,
+            adaptive_timeout: self.adaptive_timeout,
+            min_timeout: self.min_timeout,
+            max_timeout: self.max_timeout,
+            selective: self.selective,
+            congestion: self.congestion.clone(),
+            window_budget: self.window_budget,
+        }
+    }
+}
+
+// 基于 tokio 的会话：在 Transport 上驱动协议核心的状态机，并负责文件读写和限速
 pub struct Session {
-    socket: UdpSocket,
+    transport: Transport,
     config: SessionConfig,
-
-    filename: Option<String>,
-    filesize: Option<u64>,
-    mode: Option<String>,
-    blksize: u16,
-    windowsize: u16,
-    first_data: Option<(u16, Vec<u8>)>,
+    limiter: Option<RateLimiter>,
+    shared_limiter: Option<Arc<RateLimiter>>,
+    handlers: OptionHandlers,
+    provider: Option<Arc<dyn ContentProvider>>,
+    sink: Option<Arc<dyn UploadSink>>,
+    events: Option<EventSender>,
+    // 服务端当前处理的请求的事件
+    emitter: Option<Emitter>,
+    progress: Option<watch::Sender<Progress>>,
+    // 客户端取消传输，以及取消时是否保留已下载的部分
+    cancel: Option<CancellationToken>,
+    keep_partial: bool,
+    // 服务端的请求记录，数据收完后即释放，不必等到会话结束
+    request: Option<RequestGuard>,
 }
 
 impl Session {
-    pub fn new(socket: UdpSocket, config: SessionConfig) -> Self {
+    pub fn new(transport: Transport, config: SessionConfig) -> Self {
+        let limiter = config.rate_limit.map(RateLimiter::new);
         Self {
-            socket,
+            transport,
             config,
-            filename: None,
-            filesize: None,
-            mode: None,
-            blksize: DEF_BLOCK_SIZE,
-            windowsize: DEF_WINDOW_SIZE,
-            first_data: None,
+            limiter,
+            shared_limiter: None,
+            handlers: OptionHandlers::default(),
+            provider: None,
+            sink: None,
+            events: None,
+            emitter: None,
+            progress: None,
+            cancel: None,
+            keep_partial: false,
+            request: None,
+        }
+    }
+
+    // 与其他会话共享的限速器（服务端总速率）
+    pub fn set_shared_limiter(&mut self, limiter: Option<Arc<RateLimiter>>) {
+        self.shared_limiter = limiter;
+    }
+
+    // 服务端注册的自定义选项处理函数
+    pub(crate) fn set_option_handlers(&mut self, handlers: OptionHandlers) {
+        self.handlers = handlers;
+    }
+
+    pub(crate) fn set_content_provider(&mut self, provider: Option<Arc<dyn ContentProvider>>) {
+        self.provider = provider;
+    }
+
+    pub(crate) fn set_upload_sink(&mut self, sink: Option<Arc<dyn UploadSink>>) {
+        self.sink = sink;
+    }
+
+    pub(crate) fn set_events(&mut self, events: EventSender) {
+        self.events = Some(events);
+    }
+
+    // 客户端的进度通知
+    pub(crate) fn set_progress(&mut self, progress: watch::Sender<Progress>) {
+        self.progress = Some(progress);
+    }
+
+    pub(crate) fn set_cancel_token(&mut self, cancel: CancellationToken) {
+        self.cancel = Some(cancel);
+    }
+
+    pub(crate) fn set_request_guard(&mut self, request: RequestGuard) {
+        self.request = Some(request);
+    }
+
+    pub(crate) fn set_keep_partial(&mut self, keep_partial: bool) {
+        self.keep_partial = keep_partial;
+    }
+
+    fn emit(&self, kind: EventKind) {
+        if let Some(emitter) = &self.emitter {
+            emitter.emit(kind);
+        }
+    }
+
+    async fn pace(&self, bytes: usize) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(bytes).await;
+        }
+        if let Some(limiter) = &self.shared_limiter {
+            limiter.acquire(bytes).await;
         }
     }
 
@@ -54,10 +189,18 @@ impl Session {
         for component in path.components() {
             match component {
                 std::path::Component::ParentDir => {
-                    return Err(anyhow!("Access denied: path traversal detected"));
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "Access denied: path traversal detected",
+                    )
+                    .into());
                 }
                 std::path::Component::RootDir | std::path::Component::Prefix(_) => {
-                    return Err(anyhow!("Access denied: absolute paths not allowed"));
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "Access denied: absolute paths not allowed",
+                    )
+                    .into());
                 }
                 _ => {}
             }
@@ -65,479 +208,508 @@ impl Session {
         Ok(self.config.directory.join(filename))
     }
 
-    pub async fn negotiation(
-        &mut self,
-        filename: String,
-        mode: String,
-        options: HashMap<String, String>,
-    ) -> anyhow::Result<()> {
-        self.filename = Some(filename.clone());
-        self.mode = Some(mode);
-
-        self.get_filesize()?;
-
-        let mut nego_options: HashMap<String, String> = HashMap::new();
-        for (key, value) in options {
-            match key.as_str() {
-                "blksize" => {
-                    self.blksize = value.parse()?;
-                    self.blksize = std::cmp::min(self.blksize, MAX_BLOCK_SIZE);
-                    self.blksize = std::cmp::max(self.blksize, MIN_BLOCK_SIZE);
-                    nego_options.insert(key, self.blksize.to_string());
-                }
-                "windowsize" => {
-                    self.windowsize = value.parse()?;
-                    nego_options.insert(key, self.windowsize.to_string());
-                }
-                "tsize" => {
-                    nego_options.insert(key, self.filesize.unwrap().to_string());
-                }
-                _ => (),
-            }
-        }
-        if !nego_options.is_empty() {
-            info!("nego: {:?}", nego_options);
-            let oack = TftpPacket::OACK(nego_options);
-            let oack = oack.serialize();
-
-            let mut retries: u8 = 0;
-            loop {
-                self.socket.send(&oack).await?;
-                let block = match timeout(
-                    Duration::from_millis(self.config.timeout),
-                    self.recv_ack(),
-                )
-                .await
-                {
-                    Ok(res) => res?,
-                    Err(_) => {
-                        println!("timeout");
-                        retries += 1;
-                        if retries == self.config.retry {
-                            self.send_error(format!("Max retries reached")).await?;
-                        }
-                        continue;
-                    }
-                };
-                if block == 0 {
-                    break;
-                } else {
-                    self.send_error(format!("expect block #0, but #{block}"))
-                        .await?;
-                }
-            }
-        }
-
-        Ok(())
-    }
-
-    pub async fn send_error(&self, msg: String) -> anyhow::Result<()> {
+    // 通知对端错误，返回的错误带有错误码
+    pub async fn send_error<T>(&self, code: u16, msg: String) -> anyhow::Result<T> {
         let pkt = TftpPacket::ERROR {
-            code: 0,
+            code,
             msg: msg.clone(),
         };
         let bytes = pkt.serialize();
-        self.socket.send(&bytes).await?;
-        Err(anyhow!(msg))
+        self.transport.send(&bytes).await?;
+        Err(TransferError { code, msg }.into())
     }
 
-    fn get_filesize(&mut self) -> anyhow::Result<()> {
-        let filename = self.filename.as_ref().ok_or(anyhow!("No filename"))?.clone();
-        let path = self.resolve_path(&filename)?;
-        let metadata = fs::metadata(path)?;
-        self.filesize = Some(metadata.len());
-        Ok(())
+    // 本地中止传输（如服务停机）：通知对端并发出 Failed 事件
+    pub(crate) async fn abort(&self, code: u16, msg: String) -> anyhow::Result<()> {
+        self.emit(EventKind::Failed {
+            code,
+            reason: msg.clone(),
+        });
+        self.send_error(code, msg).await
     }
 
-    async fn recv_ack(&self) -> anyhow::Result<u16> {
-        let mut buf = [0; 100];
-        let n = self.socket.recv(&mut buf).await?;
-
-        match TftpPacket::deserialize(&buf[..n])? {
-            TftpPacket::ACK(ack) => Ok(ack),
-            TftpPacket::ERROR { code, msg } => {
-                Err(anyhow!("Get error packet: code: {code}, msg: {msg}"))
+    // 服务端处理一个 RRQ/WRQ 请求直到传输结束
+    pub async fn serve(&mut self, request: TftpPacket) -> anyhow::Result<()> {
+        let (filename, mode, options, write) = match request {
+            TftpPacket::RRQ {
+                filename,
+                mode,
+                options,
+            } => (filename, mode, options, false),
+            TftpPacket::WRQ {
+                filename,
+                mode,
+                options,
+            } => (filename, mode, options, true),
+            _ => return Err(anyhow!("Not a request packet")),
+        };
+        let peer = self.transport.peer_addr()?;
+        if let Some(events) = self.events.clone() {
+            let emitter = Emitter::new(events, peer, filename.clone(), mode, write);
+            self.emitter = Some(emitter);
+        }
+        self.emit(EventKind::RequestReceived);
+        match self.serve_request(peer, filename, options, write).await {
+            Ok(stats) => {
+                self.emit(EventKind::Completed(stats));
+                Ok(())
+            }
+            Err(e) => {
+                self.emit(EventKind::Failed {
+                    code: error_code(&e),
+                    reason: e.to_string(),
+                });
+                Err(e)
             }
-            _ => Err(anyhow!("Not ack packet")),
         }
     }
 
-    pub async fn send_file(&mut self) -> anyhow::Result<()> {
-        let start = Instant::now();
-        let mut gbn = self.config.gbn;
-        if gbn && self.windowsize == 1 {
-            self.windowsize = 4;
+    async fn serve_request(
+        &mut self,
+        peer: SocketAddr,
+        filename: String,
+        options: TftpOptions,
+        write: bool,
+    ) -> anyhow::Result<TransferStats> {
+        let config = self.config.transfer_config();
+        let mut ctx = RequestContext {
+            peer,
+            filename,
+            write,
+            options: &options,
+        };
+        let accepted = match self.handlers.apply(&mut ctx) {
+            Ok(accepted) => accepted,
+            Err(reason) => return self.send_error(ERR_OPTION, reason).await,
+        };
+        if write {
+            let storage = match self.open_sink(&ctx) {
+                Ok(storage) => storage,
+                Err(e) => return self.send_error(error_code(&e), e.to_string()).await,
+            };
+            self.set_event_path(&storage);
+            let mut transfer = ServerTransfer::write(options, config).with_options(accepted);
+            self.negotiated(&mut transfer);
+            self.drive(&mut transfer, storage, None).await
         } else {
-            gbn = false;
+            let (storage, filesize) = match self.open_source(&ctx) {
+                Ok(res) => res,
+                Err(e) => return self.send_error(error_code(&e), e.to_string()).await,
+            };
+            self.set_event_path(&storage);
+            let mut transfer =
+                ServerTransfer::read(options, filesize, config).with_options(accepted);
+            self.negotiated(&mut transfer);
+            self.drive(&mut transfer, storage, None).await
         }
-        let mut send_buf: Vec<u8> = vec![0; usize::from(self.blksize)];
-        let path = self.resolve_path(self.filename.as_ref().unwrap())?;
-        let mut file = File::open(path)?;
-        let mut window = Window::new(self.windowsize);
-        let mut size: usize = 0;
-        let mut finish = false;
-        let mut retries: u8 = 0;
-        while !finish {
-            for block in &mut window {
-                if let Ok(_) = self.socket.try_peek_sender() {
-                    window.next_send = window.next_send.wrapping_sub(1);
-                    break;
-                }
+    }
 
-                size = file.read(&mut send_buf[..])?;
-                let data = &send_buf[..size];
-                let pkt = TftpPacket::DATA {
-                    block: block,
-                    data: data.to_vec(),
-                };
-
-                self.socket.send(&pkt.serialize()).await?;
-                if size < usize::from(self.blksize) {
-                    finish = true;
-                    break;
-                }
-            }
-            let ack_block =
-                match timeout(Duration::from_millis(self.config.timeout), self.recv_ack()).await {
-                    Ok(res) => {
-                        retries = 0;
-                        res?
-                    }
-                    Err(_) => {
-                        warn!("timeout");
-                        retries += 1;
-                        if retries == self.config.retry {
-                            return self.send_error(format!("Max retries reached")).await;
-                        }
-                        window.start.wrapping_sub(1)
-                    }
-                };
-            let offset = window.update(ack_block, gbn);
-            let offset_size;
-            if offset != 0 {
-                warn!("retrans #{}", window.next_send);
-                if offset < 0 {
-                    offset_size = (offset + 1) * (self.blksize as i64) - (size as i64);
-                } else {
-                    offset_size = offset * (self.blksize as i64);
-                }
-                file.seek(SeekFrom::Current(offset_size))?;
-                finish = false;
-            }
+    // 之后的事件带上本地文件的绝对路径
+    fn set_event_path(&mut self, storage: &Storage) {
+        if let Some(emitter) = &mut self.emitter
+            && let Storage::File { path, .. } = storage
+        {
+            emitter.path = std::path::absolute(path).ok();
         }
+    }
 
-        let cost = start.elapsed();
-        info!(
-            "cost: {:.3}s, speed: {:.2} MB/s",
-            cost.as_secs_f64(),
-            self.filesize.unwrap() as f64 / cost.as_secs_f64() / 1024.0 / 1024.0
-        );
-        Ok(())
+    fn negotiated(&self, transfer: &mut ServerTransfer) {
+        if let Some(options) = transfer.negotiated() {
+            self.emit(EventKind::Negotiated(options.clone()));
+        }
     }
 
-    pub async fn recv_file(&mut self) -> anyhow::Result<()> {
-        let start = Instant::now();
-        let path = self.resolve_path(self.filename.as_ref().unwrap())?;
-        let mut file = File::create(path)?;
-        let mut expected_block: u16 = 1;
-        let mut retries: u8 = 0;
-        let mut total_size: u64 = 0;
-        let mut window_count: u16 = 0;
-
-        // Handle buffered first DATA packet (server responded with DATA#1 instead of OACK)
-        if let Some((block, data)) = self.first_data.take() {
-            if block == expected_block {
-                file.write_all(&data)?;
-                total_size += data.len() as u64;
-                window_count += 1;
-                let is_last = data.len() < self.blksize as usize;
-                if is_last || window_count >= self.windowsize {
-                    let ack = TftpPacket::ACK(block);
-                    self.socket.send(&ack.serialize()).await?;
-                    window_count = 0;
-                }
-                if is_last {
-                    let cost = start.elapsed();
-                    info!(
-                        "recv cost: {:.3}s, size: {} bytes, speed: {:.2} MB/s",
-                        cost.as_secs_f64(),
-                        total_size,
-                        total_size as f64 / cost.as_secs_f64() / 1024.0 / 1024.0
-                    );
-                    return Ok(());
-                }
-                expected_block = expected_block.wrapping_add(1);
-            }
+    // RRQ 的数据来源：内容提供者生成的内容，或服务目录下的文件
+    fn open_source(
+        &self,
+        ctx: &RequestContext<'_>,
+    ) -> anyhow::Result<(Storage<'static>, Option<u64>)> {
+        if let Some(provider) = &self.provider
+            && let Some(content) = provider.provide(ctx)?
+        {
+            info!("{} {} served by content provider", ctx.peer, ctx.filename);
+            return Ok((Storage::Reader(content.reader), content.size));
         }
+        let path = self.resolve_path(&ctx.filename)?;
+        let filesize = fs::metadata(&path)?.len();
+        Ok((Storage::new(path, false), Some(filesize)))
+    }
 
-        loop {
-            let mut buf = vec![0u8; self.blksize as usize + 4];
-            match timeout(
-                Duration::from_millis(self.config.timeout),
-                self.socket.recv(&mut buf),
-            )
-            .await
-            {
-                Ok(Ok(n)) => {
-                    retries = 0;
-                    match TftpPacket::deserialize(&buf[..n])? {
-                        TftpPacket::DATA { block, data } => {
-                            if block == expected_block {
-                                file.write_all(&data)?;
-                                total_size += data.len() as u64;
-                                window_count += 1;
-                                let is_last = data.len() < self.blksize as usize;
-
-                                // RFC 7440: only ACK at window boundary or last packet
-                                if is_last || window_count >= self.windowsize {
-                                    let ack = TftpPacket::ACK(block);
-                                    self.socket.send(&ack.serialize()).await?;
-                                    window_count = 0;
-                                }
-
-                                if is_last {
-                                    break;
-                                }
-                                expected_block = expected_block.wrapping_add(1);
-                            } else {
-                                let ack = TftpPacket::ACK(expected_block.wrapping_sub(1));
-                                self.socket.send(&ack.serialize()).await?;
-                                window_count = 0;
-                            }
-                        }
-                        TftpPacket::ERROR { code, msg } => {
-                            return Err(anyhow!("Peer error: code={}, msg={}", code, msg));
-                        }
-                        _ => {}
-                    }
-                }
-                Ok(Err(e)) => return Err(e.into()),
-                Err(_) => {
-                    warn!("timeout waiting for DATA#{}", expected_block);
-                    retries += 1;
-                    if retries >= self.config.retry {
-                        return self.send_error("Max retries reached".to_string()).await;
-                    }
-                    let ack = TftpPacket::ACK(expected_block.wrapping_sub(1));
-                    self.socket.send(&ack.serialize()).await?;
-                    window_count = 0;
-                }
-            }
+    // WRQ 的写入目标：上传接收者，或服务目录下的文件
+    fn open_sink(&self, ctx: &RequestContext<'_>) -> anyhow::Result<Storage<'static>> {
+        if let Some(sink) = &self.sink
+            && let Some(upload) = sink.open(ctx)?
+        {
+            info!("{} {} received by upload sink", ctx.peer, ctx.filename);
+            return Ok(Storage::Upload(Some(upload)));
         }
+        let path = self.resolve_path(&ctx.filename)?;
+        Ok(Storage::new(path, true))
+    }
 
-        let cost = start.elapsed();
-        info!(
-            "recv cost: {:.3}s, size: {} bytes, speed: {:.2} MB/s",
-            cost.as_secs_f64(),
-            total_size,
-            total_size as f64 / cost.as_secs_f64() / 1024.0 / 1024.0
+    // 客户端下载文件
+    pub async fn get(
+        &mut self,
+        server_addr: SocketAddr,
+        filename: &str,
+        blksize: u16,
+        windowsize: u16,
+    ) -> anyhow::Result<TransferStats> {
+        let path = self.resolve_path(filename)?;
+        let mut transfer =
+            ClientTransfer::get(filename, blksize, windowsize, self.config.transfer_config());
+        self.drive(&mut transfer, Storage::new(path, true), Some(server_addr))
+            .await
+    }
+
+    // 客户端上传文件
+    pub async fn put(
+        &mut self,
+        server_addr: SocketAddr,
+        filename: &str,
+        blksize: u16,
+        windowsize: u16,
+    ) -> anyhow::Result<TransferStats> {
+        let path = self.resolve_path(filename)?;
+        let filesize = fs::metadata(&path)?.len();
+        let mut transfer = ClientTransfer::put(
+            filename,
+            blksize,
+            windowsize,
+            Some(filesize),
+            self.config.transfer_config(),
         );
-        Ok(())
+        self.drive(&mut transfer, Storage::new(path, false), Some(server_addr))
+            .await
     }
 
-    pub async fn send_rrq(
+    // 客户端下载到 writer
+    pub async fn get_to_writer(
         &mut self,
         server_addr: SocketAddr,
         filename: &str,
+        writer: impl AsyncWrite + Send + Unpin,
         blksize: u16,
         windowsize: u16,
-    ) -> anyhow::Result<()> {
-        self.filename = Some(filename.to_string());
-        self.blksize = blksize;
-        self.windowsize = windowsize;
-
-        let mut options = HashMap::new();
-        if blksize != DEF_BLOCK_SIZE {
-            options.insert("blksize".to_string(), blksize.to_string());
-        }
-        if windowsize != DEF_WINDOW_SIZE {
-            options.insert("windowsize".to_string(), windowsize.to_string());
-        }
-        options.insert("tsize".to_string(), "0".to_string());
+    ) -> anyhow::Result<TransferStats> {
+        let mut transfer =
+            ClientTransfer::get(filename, blksize, windowsize, self.config.transfer_config());
+        let storage = Storage::Writer(Box::new(writer));
+        self.drive(&mut transfer, storage, Some(server_addr)).await
+    }
 
-        let pkt = TftpPacket::RRQ {
-            filename: filename.to_string(),
-            mode: "octet".to_string(),
-            options: options.clone(),
-        };
-        let bytes = pkt.serialize();
-        self.socket.send_to(&bytes, server_addr).await?;
+    // 客户端上传 reader 的内容，size 为 None 时不告知服务端大小
+    pub async fn put_from_reader(
+        &mut self,
+        server_addr: SocketAddr,
+        filename: &str,
+        reader: impl AsyncRead + Send + Unpin,
+        size: Option<u64>,
+        blksize: u16,
+        windowsize: u16,
+    ) -> anyhow::Result<TransferStats> {
+        let mut transfer = ClientTransfer::put(
+            filename,
+            blksize,
+            windowsize,
+            size,
+            self.config.transfer_config(),
+        );
+        let storage = Storage::Reader(Box::new(reader));
+        self.drive(&mut transfer, storage, Some(server_addr)).await
+    }
 
-        let mut retries: u8 = 0;
+    // 执行状态机的输出直到传输结束。server_addr 为客户端请求的目的地址，
+    // 收到首个回应后连接到服务端的传输端口
+    async fn drive(
+        &mut self,
+        transfer: &mut impl Transfer,
+        mut storage: Storage<'_>,
+        mut server_addr: Option<SocketAddr>,
+    ) -> anyhow::Result<TransferStats> {
+        let start = Instant::now();
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        // 读取文件的缓冲区，每块复用
+        let mut block = Vec::new();
+        let mut progress_at = start;
+        self.report_progress(transfer);
+        // 本地出错时通知对端的错误码
+        let mut abort_code = None;
+        let cancel = self.cancel.clone().unwrap_or_default();
+        let mut cancelled = false;
         loop {
-            let mut buf = vec![0u8; self.blksize as usize + 4];
-            match timeout(
-                Duration::from_millis(self.config.timeout),
-                self.socket.recv_from(&mut buf),
-            )
-            .await
-            {
-                Ok(Ok((n, peer))) => {
-                    // Connect to the server's new TID (transfer port)
-                    self.socket.connect(peer).await?;
-                    match TftpPacket::deserialize(&buf[..n])? {
-                        TftpPacket::OACK(opts) => {
-                            if let Some(v) = opts.get("blksize") {
-                                self.blksize = v.parse()?;
-                            }
-                            if let Some(v) = opts.get("windowsize") {
-                                self.windowsize = v.parse()?;
-                            }
-                            if let Some(v) = opts.get("tsize") {
-                                self.filesize = Some(v.parse()?);
-                            }
-                            info!("negotiated: {:?}", opts);
-                            let ack = TftpPacket::ACK(0);
-                            self.socket.send(&ack.serialize()).await?;
-                            break;
-                        }
-                        TftpPacket::DATA { block, data } => {
-                            // Server didn't send OACK, responded with DATA directly
-                            self.blksize = DEF_BLOCK_SIZE;
-                            self.windowsize = DEF_WINDOW_SIZE;
-                            self.first_data = Some((block, data));
-                            break;
-                        }
-                        TftpPacket::ERROR { code, msg } => {
-                            return Err(anyhow!("Server error: code={}, msg={}", code, msg));
-                        }
-                        _ => {
-                            return Err(anyhow!("Unexpected packet during RRQ negotiation"));
-                        }
+            if !cancelled && cancel.is_cancelled() {
+                cancelled = true;
+                if !self.keep_partial {
+                    storage.discard();
+                }
+                // 还没收到回应时不知道对端的传输端口，无法通知
+                if server_addr.is_some() {
+                    return Err(Cancelled.into());
+                }
+                transfer.abort(ERR_NOT_DEFINED, Cancelled.to_string());
+            }
+            // 有报文待处理时先处理，避免继续发送已过时的窗口
+            if self.transport.has_pending() {
+                let n = self.recv(&mut buf, &mut server_addr).await?;
+                transfer.on_datagram(&buf[..n], Instant::now().into_std());
+                continue;
+            }
+            match transfer.poll(Instant::now().into_std()) {
+                Output::Transmit(bytes) => {
+                    self.pace(bytes.len()).await;
+                    match server_addr {
+                        Some(addr) => self.transport.send_to(bytes, addr).await?,
+                        None => self.transport.send(bytes).await?,
+                    };
+                }
+                Output::Read(len) => match storage.read(&mut block, len).await {
+                    Ok(()) => transfer.push_block(&block),
+                    Err(e) => {
+                        abort_code = Some(error_code(&e));
+                        transfer.abort(error_code(&e), e.to_string());
+                    }
+                },
+                Output::Write(data) => match storage.write(data).await {
+                    Ok(()) => (),
+                    Err(e) => {
+                        abort_code = Some(error_code(&e));
+                        transfer.abort(error_code(&e), e.to_string());
+                    }
+                },
+                Output::Wait(wait) => {
+                    // 最后的 ACK 已发出，同样的请求可以再次被处理
+                    if transfer.is_dallying() {
+                        self.request = None;
+                    }
+                    tokio::select! {
+                        result = timeout(wait, self.recv(&mut buf, &mut server_addr)) => match result {
+                            Ok(n) => transfer.on_datagram(&buf[..n?], Instant::now().into_std()),
+                            Err(_) => transfer.on_timeout(),
+                        },
+                        _ = cancel.cancelled(), if !cancelled => (),
                     }
                 }
-                Ok(Err(e)) => return Err(e.into()),
-                Err(_) => {
-                    retries += 1;
-                    if retries >= self.config.retry {
-                        return Err(anyhow!("Max retries reached during RRQ"));
+                Output::Commit => match storage.commit().await {
+                    Ok(()) => (),
+                    Err(e) => {
+                        abort_code = Some(error_code(&e));
+                        transfer.abort(error_code(&e), e.to_string());
                     }
-                    warn!("timeout, resending RRQ");
-                    self.socket.send_to(&bytes, server_addr).await?;
+                },
+                Output::Done => break,
+                Output::Failed(_) if cancelled => return Err(Cancelled.into()),
+                Output::Failed(msg) => {
+                    let msg = msg.to_string();
+                    return Err(match abort_code {
+                        Some(code) => TransferError { code, msg }.into(),
+                        None => anyhow!(msg),
+                    });
                 }
             }
+            if (self.emitter.is_some() || self.progress.is_some())
+                && progress_at.elapsed() >= PROGRESS_INTERVAL
+            {
+                progress_at = Instant::now();
+                let stats = self.report_progress(transfer);
+                self.emit(EventKind::Progress {
+                    bytes: stats.bytes,
+                    blocks: stats.blocks,
+                });
+            }
         }
+        self.report_progress(transfer);
 
-        Ok(())
+        let mut stats = transfer.stats();
+        stats.duration = start.elapsed();
+        info!(
+            "cost: {:.3}s, size: {} bytes, speed: {:.2} MB/s, retransmits: {}, timeouts: {}",
+            stats.duration.as_secs_f64(),
+            stats.bytes,
+            stats.throughput() / 1024.0 / 1024.0,
+            stats.retransmits,
+            stats.timeouts
+        );
+        Ok(stats)
     }
 
-    pub async fn send_wrq(
-        &mut self,
-        server_addr: SocketAddr,
-        filename: &str,
-        blksize: u16,
-        windowsize: u16,
-    ) -> anyhow::Result<()> {
-        self.filename = Some(filename.to_string());
-        self.blksize = blksize;
-        self.windowsize = windowsize;
-        self.get_filesize()?;
-
-        let mut options = HashMap::new();
-        if blksize != DEF_BLOCK_SIZE {
-            options.insert("blksize".to_string(), blksize.to_string());
+    fn report_progress(&self, transfer: &impl Transfer) -> TransferStats {
+        let stats = transfer.stats();
+        if let Some(progress) = &self.progress {
+            progress.send_replace(Progress {
+                bytes: stats.bytes,
+                total: transfer.filesize(),
+            });
         }
-        if windowsize != DEF_WINDOW_SIZE {
-            options.insert("windowsize".to_string(), windowsize.to_string());
+        stats
+    }
+
+    async fn recv(
+        &mut self,
+        buf: &mut [u8],
+        server_addr: &mut Option<SocketAddr>,
+    ) -> io::Result<usize> {
+        if server_addr.is_none() {
+            return self.transport.recv(buf).await;
         }
-        options.insert("tsize".to_string(), self.filesize.unwrap().to_string());
+        let (n, peer) = self.transport.recv_from(buf).await?;
+        // Connect to the server's new TID (transfer port)
+        self.transport.connect(peer).await?;
+        *server_addr = None;
+        Ok(n)
+    }
+}
 
-        let pkt = TftpPacket::WRQ {
-            filename: filename.to_string(),
-            mode: "octet".to_string(),
-            options: options.clone(),
-        };
-        let bytes = pkt.serialize();
-        self.socket.send_to(&bytes, server_addr).await?;
+// 传输的数据：本地文件在第一次读写时才打开
+enum Storage<'a> {
+    File {
+        path: PathBuf,
+        create: bool,
+        file: Option<File>,
+    },
+    // 内容提供者生成的内容，或客户端上传的 reader
+    Reader(Box<dyn AsyncRead + Send + Unpin + 'a>),
+    // 客户端下载的 writer
+    Writer(Box<dyn AsyncWrite + Send + Unpin + 'a>),
+    // 上传接收者，提交后为 None
+    Upload(Option<Box<dyn Upload>>),
+    // 已删除的本地文件，之前排队的写入直接丢弃，不再重新创建
+    Discarded,
+}
 
-        let mut retries: u8 = 0;
-        loop {
-            let mut buf = [0u8; 1500];
-            match timeout(
-                Duration::from_millis(self.config.timeout),
-                self.socket.recv_from(&mut buf),
-            )
-            .await
-            {
-                Ok(Ok((n, peer))) => {
-                    // Connect to the server's new TID
-                    self.socket.connect(peer).await?;
-                    match TftpPacket::deserialize(&buf[..n])? {
-                        TftpPacket::OACK(opts) => {
-                            if let Some(v) = opts.get("blksize") {
-                                self.blksize = v.parse()?;
-                            }
-                            if let Some(v) = opts.get("windowsize") {
-                                self.windowsize = v.parse()?;
-                            }
-                            info!("WRQ negotiated: {:?}", opts);
-                            break;
-                        }
-                        TftpPacket::ACK(0) => {
-                            self.blksize = DEF_BLOCK_SIZE;
-                            self.windowsize = DEF_WINDOW_SIZE;
-                            break;
-                        }
-                        TftpPacket::ERROR { code, msg } => {
-                            return Err(anyhow!("Server error: code={}, msg={}", code, msg));
-                        }
-                        _ => return Err(anyhow!("Unexpected packet during WRQ negotiation")),
-                    }
-                }
-                Ok(Err(e)) => return Err(e.into()),
-                Err(_) => {
-                    retries += 1;
-                    if retries >= self.config.retry {
-                        return Err(anyhow!("Max retries reached during WRQ"));
-                    }
-                    warn!("timeout, resending WRQ");
-                    self.socket.send_to(&bytes, server_addr).await?;
-                }
+impl Storage<'_> {
+    fn new(path: PathBuf, create: bool) -> Self {
+        Storage::File {
+            path,
+            create,
+            file: None,
+        }
+    }
+
+    // 读取最多 len 字节到 buf，不足 len 时说明已到末尾
+    async fn read(&mut self, buf: &mut Vec<u8>, len: usize) -> anyhow::Result<()> {
+        buf.clear();
+        buf.reserve(len);
+        match self {
+            Storage::File { .. } => {
+                self.file()?.take(len as u64).read_to_end(buf)?;
+            }
+            Storage::Reader(reader) => {
+                reader.take(len as u64).read_to_end(buf).await?;
+            }
+            Storage::Writer(_) | Storage::Upload(_) | Storage::Discarded => {
+                return Err(anyhow!("storage is write-only"));
             }
         }
+        Ok(())
+    }
 
+    async fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Storage::Writer(writer) => writer.write_all(data).await?,
+            Storage::Upload(Some(upload)) => upload.write(data)?,
+            Storage::Discarded => (),
+            _ => self.file()?.write_all(data)?,
+        }
         Ok(())
     }
 
-    pub async fn negotiation_wrq(
-        &mut self,
-        filename: String,
-        mode: String,
-        options: HashMap<String, String>,
-    ) -> anyhow::Result<()> {
-        self.filename = Some(filename);
-        self.mode = Some(mode);
-
-        let mut nego_options: HashMap<String, String> = HashMap::new();
-        for (key, value) in options {
-            match key.as_str() {
-                "blksize" => {
-                    self.blksize = value.parse()?;
-                    self.blksize = std::cmp::min(self.blksize, MAX_BLOCK_SIZE);
-                    self.blksize = std::cmp::max(self.blksize, MIN_BLOCK_SIZE);
-                    nego_options.insert(key, self.blksize.to_string());
+    // 数据已全部收到，上传接收者提交数据，writer 刷新缓冲
+    async fn commit(&mut self) -> anyhow::Result<()> {
+        match self {
+            Storage::Writer(writer) => writer.flush().await?,
+            Storage::Upload(upload) => {
+                if let Some(upload) = upload.take() {
+                    upload.commit()?;
                 }
-                "windowsize" => {
-                    self.windowsize = value.parse()?;
-                    nego_options.insert(key, self.windowsize.to_string());
-                }
-                "tsize" => {
-                    self.filesize = Some(value.parse()?);
-                    nego_options.insert(key, value);
-                }
-                _ => (),
             }
+            _ => (),
         }
+        Ok(())
+    }
 
-        if !nego_options.is_empty() {
-            info!("wrq nego: {:?}", nego_options);
-            let oack = TftpPacket::OACK(nego_options);
-            self.socket.send(&oack.serialize()).await?;
-        } else {
-            let ack = TftpPacket::ACK(0);
-            self.socket.send(&ack.serialize()).await?;
+    // 删除本次传输创建的文件
+    fn discard(&mut self) {
+        let Storage::File {
+            path,
+            create: true,
+            file,
+        } = self
+        else {
+            return;
+        };
+        if file.take().is_some() {
+            info!("remove partial file {}", path.display());
+            if let Err(e) = fs::remove_file(&*path) {
+                warn!("failed to remove {}: {e}", path.display());
+            }
         }
+        *self = Storage::Discarded;
+    }
 
-        Ok(())
+    fn file(&mut self) -> io::Result<&mut File> {
+        let Storage::File { path, create, file } = self else {
+            return Err(io::Error::other("not a local file"));
+        };
+        if file.is_none() {
+            *file = Some(if *create {
+                File::create(&*path)?
+            } else {
+                File::open(&*path)?
+            });
+        }
+        Ok(file.as_mut().unwrap())
+    }
+}
+
+// 未提交就结束（出错、被中止）的上传交给接收者丢弃
+impl Drop for Storage<'_> {
+    fn drop(&mut self) {
+        if let Storage::Upload(upload) = self
+            && let Some(upload) = upload.take()
+        {
+            upload.abort();
+        }
+    }
+}
+
+// 传输被调用方取消，已尽可能通知对端
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Transfer cancelled")
+    }
+}
+
+impl error::Error for Cancelled {}
+
+// 已通知对端的错误
+#[derive(Debug)]
+struct TransferError {
+    code: u16,
+    msg: String,
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl error::Error for TransferError {}
+
+// 将本地错误映射为 TFTP 错误码
+fn error_code(e: &anyhow::Error) -> u16 {
+    if let Some(e) = e.downcast_ref::<TransferError>() {
+        return e.code;
+    }
+    match e.downcast_ref::<io::Error>().map(|e| e.kind()) {
+        Some(io::ErrorKind::NotFound) => ERR_FILE_NOT_FOUND,
+        Some(io::ErrorKind::PermissionDenied) => ERR_ACCESS_VIOLATION,
+        Some(io::ErrorKind::AlreadyExists) => ERR_FILE_EXISTS,
+        Some(io::ErrorKind::StorageFull) => ERR_DISK_FULL,
+        _ => ERR_NOT_DEFINED,
     }
 }
@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+// 一次传输的统计
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransferStats {
+    // 读取或写入存储的数据字节数和块数
+    pub bytes: u64,
+    pub blocks: u64,
+    // 由驱动方统计，协议核心不知道传输何时结束
+    pub duration: Duration,
+    // 重发的报文数（DATA、ACK、请求、OACK）
+    pub retransmits: u64,
+    pub timeouts: u64,
+    // 发送端收到的已确认过的块的 ACK
+    pub duplicate_acks: u64,
+    // 协商后的参数，协商完成前为 0
+    pub blksize: u16,
+    pub windowsize: u16,
+    // 平滑 RTT 估计（RFC 6298），没有有效采样时为 None
+    pub rtt: Option<Duration>,
+}
+
+impl TransferStats {
+    // 平均速率（字节/秒）
+    pub fn throughput(&self) -> f64 {
+        if self.duration.is_zero() {
+            return 0.0;
+        }
+        self.bytes as f64 / self.duration.as_secs_f64()
+    }
+}
+
+// 传输进度，total 为对端告知（tsize）或本地文件的大小，未知时为 None
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub bytes: u64,
+    pub total: Option<u64>,
+}
@@ -0,0 +1,94 @@
+use crate::server::RouteGuard;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+// 会话收发报文的通道
+pub enum Transport {
+    // 会话独占的套接字（RFC 1350 每个传输一个 TID）
+    Socket(UdpSocket),
+    // 与监听端口共享的套接字，服务端按对端地址把报文分发到 rx
+    Shared {
+        socket: Arc<UdpSocket>,
+        peer: SocketAddr,
+        rx: mpsc::Receiver<Vec<u8>>,
+        // 会话结束（Transport 被释放）时从路由表注销
+        _route: RouteGuard,
+    },
+}
+
+impl From<UdpSocket> for Transport {
+    fn from(socket: UdpSocket) -> Self {
+        Transport::Socket(socket)
+    }
+}
+
+impl Transport {
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Socket(socket) => socket.send(buf).await,
+            Transport::Shared { socket, peer, .. } => socket.send_to(buf, *peer).await,
+        }
+    }
+
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        match self {
+            Transport::Socket(socket) => socket.send_to(buf, addr).await,
+            Transport::Shared { socket, .. } => socket.send_to(buf, addr).await,
+        }
+    }
+
+    pub async fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Socket(socket) => socket.recv(buf).await,
+            Transport::Shared { rx, .. } => {
+                let pkt = rx.recv().await.ok_or(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "session channel closed",
+                ))?;
+                let n = pkt.len().min(buf.len());
+                buf[..n].copy_from_slice(&pkt[..n]);
+                Ok(n)
+            }
+        }
+    }
+
+    pub async fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self {
+            Transport::Socket(socket) => socket.recv_from(buf).await,
+            Transport::Shared { peer, .. } => {
+                let peer = *peer;
+                let n = self.recv(buf).await?;
+                Ok((n, peer))
+            }
+        }
+    }
+
+    pub async fn connect(&self, addr: SocketAddr) -> io::Result<()> {
+        match self {
+            Transport::Socket(socket) => socket.connect(addr).await,
+            Transport::Shared { peer, .. } if *peer == addr => Ok(()),
+            Transport::Shared { .. } => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "shared transport is bound to another peer",
+            )),
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Transport::Socket(socket) => socket.peer_addr(),
+            Transport::Shared { peer, .. } => Ok(*peer),
+        }
+    }
+
+    // 是否已有待读取的报文（用于发送窗口时及时处理 ACK）
+    pub fn has_pending(&self) -> bool {
+        match self {
+            Transport::Socket(socket) => socket.try_peek_sender().is_ok(),
+            Transport::Shared { rx, .. } => !rx.is_empty(),
+        }
+    }
+}
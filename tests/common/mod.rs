@@ -0,0 +1,39 @@
+// 集成测试共用的服务端启动和临时目录
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use tempfile::TempDir;
+use tftp::{SessionConfig, TftpServer};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+// 测试结束时随 TempDir 一起删除
+pub fn test_dir(name: &str) -> TempDir {
+    tempfile::Builder::new()
+        .prefix(&format!("tftp-{name}-"))
+        .tempdir()
+        .unwrap()
+}
+
+// 地址由 start() 绑定的套接字决定，默认不等待会话结束
+pub fn server(config: SessionConfig) -> TftpServer {
+    TftpServer::new(SocketAddr::from(([127, 0, 0, 1], 0)), config)
+        .with_drain_timeout(Duration::ZERO)
+}
+
+// 先绑定监听端口再启动，返回时服务端已可接收请求
+pub async fn start(
+    server: TftpServer,
+) -> (
+    SocketAddr,
+    CancellationToken,
+    JoinHandle<anyhow::Result<()>>,
+) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let shutdown = server.shutdown_token();
+    let handle = tokio::spawn(async move { server.run_with_socket(socket).await });
+    (addr, shutdown, handle)
+}
@@ -0,0 +1,448 @@
+// 网络损伤测试：客户端和服务端的协议状态机经过模拟链路在虚拟时钟上运行，
+// 丢包、重复、乱序、延迟和截断都由种子决定，同一种子重放出完全相同的报文序列
+use std::time::{Duration, Instant};
+use tftp::Congestion;
+use tftp::proto::{ClientTransfer, Output, Request, ServerTransfer, Transfer, TransferConfig};
+
+// 虚拟时间超过该值仍未结束视为卡死
+const TIME_LIMIT: Duration = Duration::from_secs(600);
+// 乱序报文的额外延迟，小于超时，乱序不会变成丢包
+const REORDER_LAG: Duration = Duration::from_millis(5);
+
+// 网络损伤参数，概率均为每个报文独立判定
+#[derive(Clone, Copy, Debug, Default)]
+struct Impairment {
+    loss: f64,
+    duplicate: f64,
+    // 报文额外滞后 REORDER_LAG，被之后发出的报文超过
+    reorder: f64,
+    // 随机延迟的上限
+    delay: Duration,
+    // 截断为不足 4 字节的残包。截断 DATA 的负载会被当作最后一块，
+    // TFTP 本身无法识别，因此只模拟报头不完整的情况
+    truncate: f64,
+}
+
+// 由种子决定的伪随机数（xorshift64*）
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && (self.next() >> 11) as f64 / (1u64 << 53) as f64 <= p
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        if n == 0 { 0 } else { self.next() % n }
+    }
+}
+
+// 链路的一个方向，报文按损伤参数处理后在虚拟时间到达对端
+struct Direction {
+    impairment: Impairment,
+    rng: Rng,
+    // 在途的报文：到达时间、发送序号（同时到达时按发送顺序）和内容
+    in_flight: Vec<(Instant, u64, Vec<u8>)>,
+    seq: u64,
+}
+
+impl Direction {
+    fn new(impairment: Impairment, seed: u64) -> Self {
+        Self {
+            impairment,
+            rng: Rng::new(seed),
+            in_flight: Vec::new(),
+            seq: 0,
+        }
+    }
+
+    fn forward(&mut self, mut pkt: Vec<u8>, now: Instant) {
+        let imp = self.impairment;
+        if self.rng.chance(imp.loss) {
+            return;
+        }
+        if self.rng.chance(imp.truncate) {
+            let len = self.rng.below(pkt.len().min(4) as u64);
+            pkt.truncate(len as usize);
+        }
+        let copies = if self.rng.chance(imp.duplicate) { 2 } else { 1 };
+        let lag = if self.rng.chance(imp.reorder) {
+            REORDER_LAG
+        } else {
+            Duration::ZERO
+        };
+        for _ in 0..copies {
+            self.send(pkt.clone(), now + lag);
+        }
+    }
+
+    fn send(&mut self, pkt: Vec<u8>, at: Instant) {
+        let delay = self.rng.below(self.impairment.delay.as_millis() as u64);
+        self.in_flight
+            .push((at + Duration::from_millis(delay), self.seq, pkt));
+        self.seq += 1;
+    }
+
+    fn next_arrival(&self) -> Option<Instant> {
+        self.in_flight.iter().map(|(at, _, _)| *at).min()
+    }
+
+    // 取出 now 之前到达的报文，按到达顺序排列
+    fn arrived(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        self.in_flight.sort_by_key(|(at, seq, _)| (*at, *seq));
+        let n = self.in_flight.partition_point(|(at, _, _)| *at <= now);
+        self.in_flight.drain(..n).map(|(_, _, pkt)| pkt).collect()
+    }
+}
+
+// 一端的传输：收件箱、读取的数据源和写入的数据
+struct Endpoint {
+    transfer: Box<dyn Transfer>,
+    inbox: Vec<Vec<u8>>,
+    source: Vec<u8>,
+    pos: usize,
+    written: Vec<u8>,
+    deadline: Option<Instant>,
+    result: Option<Result<(), String>>,
+}
+
+impl Endpoint {
+    fn new(transfer: Box<dyn Transfer>, source: Vec<u8>) -> Self {
+        Self {
+            transfer,
+            inbox: Vec::new(),
+            source,
+            pos: 0,
+            written: Vec::new(),
+            deadline: None,
+            result: None,
+        }
+    }
+
+    // 处理收到的报文并执行输出，直到需要等待或传输结束
+    fn step(&mut self, now: Instant, outbox: &mut Vec<Vec<u8>>) {
+        let mut inbox = std::mem::take(&mut self.inbox).into_iter();
+        while self.result.is_none() {
+            if let Some(datagram) = inbox.next() {
+                self.transfer.on_datagram(&datagram, now);
+                self.deadline = None;
+                continue;
+            }
+            match self.transfer.poll(now) {
+                Output::Transmit(datagram) => outbox.push(datagram.to_vec()),
+                Output::Read(len) => {
+                    let end = (self.pos + len).min(self.source.len());
+                    self.transfer.push_block(&self.source[self.pos..end]);
+                    self.pos = end;
+                }
+                Output::Write(data) => self.written.extend_from_slice(data),
+                Output::Commit => (),
+                Output::Wait(wait) => {
+                    self.deadline.get_or_insert(now + wait);
+                    return;
+                }
+                Output::Done => self.result = Some(Ok(())),
+                Output::Failed(msg) => self.result = Some(Err(msg.to_string())),
+            }
+        }
+    }
+
+    fn pending(&self) -> bool {
+        self.result.is_none()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Case {
+    blksize: u16,
+    windowsize: u16,
+    gbn: bool,
+    selective: bool,
+    congestion: Congestion,
+    put: bool,
+    seed: u64,
+}
+
+impl Case {
+    fn config(&self) -> TransferConfig {
+        TransferConfig {
+            timeout: 50,
+            retry: 20,
+            gbn: self.gbn,
+            selective: self.selective,
+            congestion: self.congestion.clone(),
+            ..Default::default()
+        }
+    }
+
+    // 覆盖非整块、跨多个窗口和不足一块的大小
+    fn data(&self) -> Vec<u8> {
+        let len = match self.seed % 3 {
+            0 => 3 * usize::from(self.blksize),
+            1 => 40_000 + self.seed as usize % 1000,
+            _ => 123,
+        };
+        let mut rng = Rng::new(self.seed);
+        (0..len).map(|_| rng.next() as u8).collect()
+    }
+}
+
+// 模拟结束时的两端，以及按到达顺序记录的报文（虚拟时间、是否发往服务端、内容）
+struct Outcome {
+    client: Endpoint,
+    server: Option<Endpoint>,
+    trace: Vec<(Duration, bool, Vec<u8>)>,
+}
+
+// 在虚拟时钟上运行一次传输，直到两端都结束
+fn simulate(case: &Case, impairment: Impairment, data: &[u8]) -> Outcome {
+    let start = Instant::now();
+    let mut now = start;
+    let config = case.config();
+    let (transfer, source) = if case.put {
+        let len = Some(data.len() as u64);
+        let put = ClientTransfer::put("impair.bin", case.blksize, case.windowsize, len, config);
+        (put, data.to_vec())
+    } else {
+        let get = ClientTransfer::get("impair.bin", case.blksize, case.windowsize, config);
+        (get, Vec::new())
+    };
+    let mut client = Endpoint::new(Box::new(transfer), source);
+    let mut server: Option<Endpoint> = None;
+    let mut to_server = Direction::new(impairment, case.seed);
+    let mut to_client = Direction::new(impairment, case.seed ^ 0x5555);
+    let mut trace = Vec::new();
+    let mut outbox = Vec::new();
+    while client.pending() || server.as_ref().is_some_and(Endpoint::pending) {
+        client.step(now, &mut outbox);
+        for pkt in outbox.drain(..) {
+            to_server.forward(pkt, now);
+        }
+        if let Some(server) = &mut server {
+            server.step(now, &mut outbox);
+            for pkt in outbox.drain(..) {
+                to_client.forward(pkt, now);
+            }
+        }
+
+        // 时钟前进到下一个报文到达或超时
+        let endpoints = std::iter::once(&client).chain(server.as_ref());
+        let deadlines = endpoints.filter(|e| e.pending()).filter_map(|e| e.deadline);
+        let arrivals = [to_server.next_arrival(), to_client.next_arrival()];
+        let Some(next) = deadlines.chain(arrivals.into_iter().flatten()).min() else {
+            break;
+        };
+        now = now.max(next);
+        assert!(now - start < TIME_LIMIT, "{case:?} stalled");
+
+        for pkt in to_server.arrived(now) {
+            trace.push((now - start, true, pkt.clone()));
+            match &mut server {
+                // 监听端口上重复的请求由服务端忽略
+                Some(_) if matches!(pkt[..], [0, 1 | 2, ..]) => (),
+                Some(server) => server.inbox.push(pkt),
+                None => {
+                    let Some(request) = Request::parse(&pkt) else {
+                        continue;
+                    };
+                    let config = case.config();
+                    let transfer = if request.write {
+                        ServerTransfer::write(request.options, config)
+                    } else {
+                        let filesize = Some(data.len() as u64);
+                        ServerTransfer::read(request.options, filesize, config)
+                    };
+                    let source = if case.put { Vec::new() } else { data.to_vec() };
+                    server = Some(Endpoint::new(Box::new(transfer), source));
+                }
+            }
+        }
+        for pkt in to_client.arrived(now) {
+            trace.push((now - start, false, pkt.clone()));
+            client.inbox.push(pkt);
+        }
+        // 同时到达的报文先于超时处理
+        for endpoint in std::iter::once(&mut client).chain(server.as_mut()) {
+            if endpoint.pending()
+                && endpoint.inbox.is_empty()
+                && endpoint.deadline.is_some_and(|d| d <= now)
+            {
+                endpoint.deadline = None;
+                endpoint.transfer.on_timeout();
+            }
+        }
+    }
+    Outcome {
+        client,
+        server,
+        trace,
+    }
+}
+
+// 失败信息中带有种子，设置 TFTP_TEST_SEED 即可重放完全相同的报文序列
+fn base_seed() -> u64 {
+    std::env::var("TFTP_TEST_SEED")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1)
+}
+
+// 完成一次传输，检查接收端的数据与发送端一致
+fn transfer(case: &Case, impairment: Impairment) {
+    let data = case.data();
+    let outcome = simulate(case, impairment, &data);
+    if case.put {
+        // 服务端在提交后才确认最后一块，客户端成功时服务端也已收完
+        let server = outcome.server.expect("server never started");
+        assert_eq!(server.result, Some(Ok(())), "{case:?}");
+        assert!(server.written == data, "{case:?}: file differs");
+        // 最后的 ACK 和等待期间的重传都丢失时，发送端无法得知对端已收完（RFC 1350），
+        // 只在客户端确实没有收到最后的 ACK 时允许重试耗尽
+        let last = (data.len() / usize::from(case.blksize) + 1) as u16;
+        let last_ack = [0, 4, (last >> 8) as u8, last as u8];
+        let acked = outcome
+            .trace
+            .iter()
+            .any(|(_, to_server, pkt)| !to_server && pkt[..] == last_ack);
+        if acked || outcome.client.result != Some(Err("Max retries reached".to_string())) {
+            assert_eq!(outcome.client.result, Some(Ok(())), "{case:?}");
+        }
+    } else {
+        assert_eq!(outcome.client.result, Some(Ok(())), "{case:?}");
+        assert!(outcome.client.written == data, "{case:?}: file differs");
+    }
+}
+
+fn matrix() -> Vec<Case> {
+    let mut cases = Vec::new();
+    let mut seed = base_seed();
+    for blksize in [512, 1468] {
+        for windowsize in [1, 8] {
+            for (gbn, selective) in [(false, false), (true, false), (false, true), (true, true)] {
+                for congestion in [Congestion::Fixed, Congestion::Aimd] {
+                    for put in [false, true] {
+                        cases.push(Case {
+                            blksize,
+                            windowsize,
+                            gbn,
+                            selective,
+                            congestion: congestion.clone(),
+                            put,
+                            seed,
+                        });
+                        seed += 1;
+                    }
+                }
+            }
+        }
+    }
+    cases
+}
+
+// 在所有 blksize/windowsize/gbn/selective/拥塞控制/方向组合下传输
+fn run_matrix(impairment: Impairment) {
+    for case in matrix() {
+        transfer(&case, impairment);
+    }
+}
+
+fn combined() -> Impairment {
+    Impairment {
+        loss: 0.03,
+        duplicate: 0.03,
+        reorder: 0.05,
+        delay: Duration::from_millis(10),
+        truncate: 0.02,
+    }
+}
+
+#[test]
+fn clean_network() {
+    run_matrix(Impairment::default());
+}
+
+#[test]
+fn packet_loss() {
+    let impairment = Impairment {
+        loss: 0.05,
+        ..Default::default()
+    };
+    run_matrix(impairment);
+}
+
+#[test]
+fn packet_duplication() {
+    let impairment = Impairment {
+        duplicate: 0.1,
+        ..Default::default()
+    };
+    run_matrix(impairment);
+}
+
+#[test]
+fn packet_reordering() {
+    let impairment = Impairment {
+        reorder: 0.1,
+        ..Default::default()
+    };
+    run_matrix(impairment);
+}
+
+#[test]
+fn packet_delay() {
+    let impairment = Impairment {
+        delay: Duration::from_millis(20),
+        ..Default::default()
+    };
+    run_matrix(impairment);
+}
+
+#[test]
+fn packet_truncation() {
+    let impairment = Impairment {
+        truncate: 0.05,
+        ..Default::default()
+    };
+    run_matrix(impairment);
+}
+
+#[test]
+fn combined_impairments() {
+    run_matrix(combined());
+}
+
+// 同一种子得到完全相同的报文序列和统计，不同种子的损伤不同
+#[test]
+fn same_seed_replays_same_packets() {
+    for case in matrix() {
+        let data = case.data();
+        let first = simulate(&case, combined(), &data);
+        let second = simulate(&case, combined(), &data);
+        assert!(first.trace == second.trace, "{case:?}");
+        assert_eq!(
+            first.client.transfer.stats(),
+            second.client.transfer.stats(),
+            "{case:?}"
+        );
+    }
+
+    let case = matrix().remove(2);
+    let data = case.data();
+    let first = simulate(&case, combined(), &data);
+    let other = Case {
+        seed: case.seed + 1000,
+        ..case.clone()
+    };
+    let second = simulate(&other, combined(), &data);
+    assert!(first.trace != second.trace);
+}
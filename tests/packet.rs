@@ -0,0 +1,196 @@
+use tftp::packet::{PacketError, TftpOptions, TftpPacket, TftpPacketRef};
+
+fn roundtrip(pkt: TftpPacket) {
+    let bytes = pkt.serialize();
+    assert_eq!(TftpPacket::deserialize(&bytes).unwrap(), pkt, "{bytes:?}");
+}
+
+#[test]
+fn serialize_roundtrip() {
+    let options = TftpOptions::from_iter([("blksize", "1468"), ("tsize", "0")]);
+    roundtrip(TftpPacket::RRQ {
+        filename: "a.bin".to_string(),
+        mode: "octet".to_string(),
+        options: options.clone(),
+    });
+    roundtrip(TftpPacket::WRQ {
+        filename: String::new(),
+        mode: "octet".to_string(),
+        options: TftpOptions::new(),
+    });
+    roundtrip(TftpPacket::DATA {
+        block: 65535,
+        data: Vec::new(),
+    });
+    roundtrip(TftpPacket::ACK(0));
+    roundtrip(TftpPacket::ERROR {
+        code: 8,
+        msg: String::new(),
+    });
+    roundtrip(TftpPacket::OACK(options));
+    // 没有确认任何选项的 OACK 只有操作码
+    roundtrip(TftpPacket::OACK(TftpOptions::new()));
+}
+
+// 截断或格式错误的报文返回对应的错误而不是 panic
+#[test]
+fn malformed_packets_are_rejected() {
+    let cases: &[(&[u8], PacketError)] = &[
+        (b"", PacketError::Truncated),
+        (b"\x00", PacketError::Truncated),
+        (b"\x00\x03\x01", PacketError::Truncated),
+        (b"\x00\x04", PacketError::Truncated),
+        (b"\x00\x04\x01", PacketError::Truncated),
+        (b"\x00\x05\x00\x01", PacketError::MissingTerminator),
+        (b"\x00\x05\x00\x01msg", PacketError::MissingTerminator),
+        (b"\x00\x01", PacketError::MissingTerminator),
+        (b"\x00\x01a.bin", PacketError::MissingTerminator),
+        (b"\x00\x01a.bin\x00octet", PacketError::MissingTerminator),
+        (
+            b"\x00\x01a.bin\x00octet\x00blksize",
+            PacketError::MissingTerminator,
+        ),
+        (
+            b"\x00\x01a.bin\x00octet\x00blksize\x001024",
+            PacketError::MissingTerminator,
+        ),
+        (b"\x00\x06tsize", PacketError::MissingTerminator),
+        (b"\x00\x06\xff\x00", PacketError::InvalidUtf8),
+        (b"\x00\x01\xc3\x28\x00octet\x00", PacketError::InvalidUtf8),
+        (
+            b"\x00\x06tsize\x000\x00tsize\x001\x00",
+            PacketError::DuplicateOption("tsize".to_string()),
+        ),
+        (
+            b"\x00\x06blksize\x00512\x00BLKSIZE\x001024\x00",
+            PacketError::DuplicateOption("BLKSIZE".to_string()),
+        ),
+        (b"\x00\x07", PacketError::InvalidOpcode(7)),
+        (b"\x00\x00\x00\x01", PacketError::InvalidOpcode(0)),
+    ];
+    for (buf, error) in cases {
+        assert_eq!(TftpPacket::deserialize(buf).as_ref(), Err(error), "{buf:?}");
+    }
+}
+
+#[test]
+fn borrowed_packet_points_into_buffer() {
+    let buf = TftpPacket::DATA {
+        block: 7,
+        data: vec![1; 65464],
+    }
+    .serialize();
+    let TftpPacketRef::DATA { block, data } = TftpPacketRef::deserialize(&buf).unwrap() else {
+        panic!("expect DATA");
+    };
+    assert_eq!(block, 7);
+    assert!(std::ptr::eq(data, &buf[4..]));
+
+    let buf = b"\x00\x01a.bin\x00octet\x00blksize\x001468\x00tsize\x000\x00";
+    let pkt = TftpPacketRef::deserialize(buf).unwrap();
+    let TftpPacketRef::RRQ {
+        filename, options, ..
+    } = pkt
+    else {
+        panic!("expect RRQ");
+    };
+    assert_eq!(filename, "a.bin");
+    assert_eq!(options.get("blksize"), Some("1468"));
+    assert_eq!(options.get("windowsize"), None);
+    assert_eq!(
+        options.iter().collect::<Vec<_>>(),
+        [("blksize", "1468"), ("tsize", "0")]
+    );
+    assert_eq!(pkt.into_owned(), TftpPacket::deserialize(buf).unwrap());
+
+    let mut out = Vec::new();
+    pkt.append_to(&mut out);
+    assert_eq!(out, buf);
+}
+
+#[test]
+fn serialize_into_buffer() {
+    let pkt = TftpPacket::ERROR {
+        code: 1,
+        msg: "File not found".to_string(),
+    };
+    let bytes = pkt.serialize();
+    let mut buf = [0u8; 64];
+    let len = pkt.serialize_into(&mut buf).unwrap();
+    assert_eq!(&buf[..len], bytes);
+    assert_eq!(
+        pkt.serialize_into(&mut buf[..bytes.len() - 1]),
+        Err(PacketError::BufferTooSmall)
+    );
+
+    // 复用已有容量，追加在末尾
+    let mut out = Vec::with_capacity(64);
+    let ptr = out.as_ptr();
+    TftpPacketRef::ACK(3).append_to(&mut out);
+    TftpPacketRef::ACK(4).append_to(&mut out);
+    assert_eq!(out, [0, 4, 0, 3, 0, 4, 0, 4]);
+    assert_eq!(out.as_ptr(), ptr);
+}
+
+#[test]
+fn options_keep_order_and_ignore_case() {
+    let mut options =
+        TftpOptions::from_iter([("tsize", "0"), ("BLKSIZE", "1468"), ("windowsize", "8")]);
+    let rrq = TftpPacket::RRQ {
+        filename: "a.bin".to_string(),
+        mode: "octet".to_string(),
+        options: options.clone(),
+    };
+    assert_eq!(
+        rrq.serialize(),
+        b"\x00\x01a.bin\x00octet\x00tsize\x000\x00BLKSIZE\x001468\x00windowsize\x008\x00"
+    );
+
+    assert_eq!(options.get("blksize"), Some("1468"));
+    assert_eq!(options.blksize(), Ok(Some(1468)));
+    assert_eq!(options.windowsize(), Ok(Some(8)));
+    assert_eq!(options.timeout(), Ok(None));
+    // 替换已有选项时保留原来的位置和名称
+    assert_eq!(options.insert("TSIZE", 5), Some("0".to_string()));
+    assert_eq!(
+        options.try_insert("WindowSize", 1),
+        Err(PacketError::DuplicateOption("WindowSize".to_string()))
+    );
+    assert_eq!(
+        options.iter().collect::<Vec<_>>(),
+        [("tsize", "5"), ("BLKSIZE", "1468"), ("windowsize", "8")]
+    );
+    assert_eq!(options.remove("Blksize"), Some("1468".to_string()));
+    assert_eq!(options.len(), 2);
+
+    options.insert("timeout", "abc");
+    let error = options.timeout().unwrap_err();
+    assert_eq!(
+        error,
+        PacketError::InvalidOption {
+            name: "timeout".to_string(),
+            value: "abc".to_string()
+        }
+    );
+    assert_eq!(error.to_string(), "Invalid timeout: abc");
+}
+
+#[test]
+fn large_option_list_is_parsed() {
+    // 约 50 KB，接近单个 UDP 报文的上限
+    let mut buf = b"\x00\x06".to_vec();
+    for i in 0..5000 {
+        buf.extend_from_slice(format!("o{i}\x00{i}\x00").as_bytes());
+    }
+    let TftpPacketRef::OACK(options) = TftpPacketRef::deserialize(&buf).unwrap() else {
+        panic!("expect OACK");
+    };
+    assert_eq!(options.iter().count(), 5000);
+    assert_eq!(options.get("O4999"), Some("4999"));
+
+    buf.extend_from_slice(b"O2500\x001\x00");
+    assert_eq!(
+        TftpPacketRef::deserialize(&buf),
+        Err(PacketError::DuplicateOption("O2500".to_string()))
+    );
+}
@@ -0,0 +1,559 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tftp::proto::{
+    ClientTransfer, ERR_ACCESS_VIOLATION, ERR_OPTION, Output, Request, ServerTransfer, Transfer,
+    TransferConfig,
+};
+use tftp::{Congestion, CongestionControl, TftpOptions, TftpPacket};
+
+// 内存中的一端：收件箱、读取的数据源和写入的数据
+struct Endpoint {
+    transfer: Box<dyn Transfer>,
+    inbox: VecDeque<Vec<u8>>,
+    source: Vec<u8>,
+    pos: usize,
+    written: Vec<u8>,
+    deadline: Option<Instant>,
+    result: Option<Result<(), String>>,
+}
+
+impl Endpoint {
+    fn new(transfer: Box<dyn Transfer>, source: Vec<u8>) -> Self {
+        Self {
+            transfer,
+            inbox: VecDeque::new(),
+            source,
+            pos: 0,
+            written: Vec::new(),
+            deadline: None,
+            result: None,
+        }
+    }
+
+    // 执行输出直到需要等待或传输结束
+    fn step(&mut self, now: Instant, outbox: &mut Vec<Vec<u8>>) {
+        while self.result.is_none() {
+            if let Some(datagram) = self.inbox.pop_front() {
+                self.transfer.on_datagram(&datagram, now);
+                self.deadline = None;
+                continue;
+            }
+            match self.transfer.poll(now) {
+                Output::Transmit(datagram) => outbox.push(datagram.to_vec()),
+                Output::Read(len) => {
+                    let end = (self.pos + len).min(self.source.len());
+                    self.transfer.push_block(&self.source[self.pos..end]);
+                    self.pos = end;
+                }
+                Output::Write(data) => self.written.extend_from_slice(data),
+                Output::Commit => (),
+                Output::Wait(wait) => {
+                    self.deadline.get_or_insert(now + wait);
+                    return;
+                }
+                Output::Done => self.result = Some(Ok(())),
+                Output::Failed(msg) => self.result = Some(Err(msg.to_string())),
+            }
+        }
+    }
+}
+
+// 按固定间隔丢弃报文
+struct Link {
+    drop_every: Option<usize>,
+    count: usize,
+}
+
+impl Link {
+    fn deliver(&mut self, outbox: &mut Vec<Vec<u8>>, inbox: &mut VecDeque<Vec<u8>>) {
+        for datagram in outbox.drain(..) {
+            self.count += 1;
+            if self
+                .drop_every
+                .is_some_and(|n| self.count.is_multiple_of(n))
+            {
+                continue;
+            }
+            inbox.push_back(datagram);
+        }
+    }
+}
+
+// 在虚拟时钟上驱动一次传输，服务端的文件内容为 served
+fn run(
+    client: ClientTransfer,
+    upload: Vec<u8>,
+    served: Vec<u8>,
+    config: &TransferConfig,
+    drop_every: Option<usize>,
+) -> (Endpoint, Option<Endpoint>) {
+    let mut now = Instant::now();
+    let mut client = Endpoint::new(Box::new(client), upload);
+    let mut server: Option<Endpoint> = None;
+    let mut to_server = Link {
+        drop_every,
+        count: 0,
+    };
+    let mut to_client = Link {
+        drop_every,
+        count: 1,
+    };
+    let mut outbox = Vec::new();
+    for _ in 0..1_000_000 {
+        client.step(now, &mut outbox);
+        if server.is_none() {
+            // 请求本身也可能丢失，此时等待客户端重发
+            let mut inbox = VecDeque::new();
+            to_server.deliver(&mut outbox, &mut inbox);
+            if let Some(datagram) = inbox.pop_front() {
+                let request = Request::parse(&datagram).unwrap();
+                let transfer = if request.write {
+                    ServerTransfer::write(request.options, config.clone())
+                } else {
+                    ServerTransfer::read(request.options, Some(served.len() as u64), config.clone())
+                };
+                let mut endpoint = Endpoint::new(Box::new(transfer), served.clone());
+                endpoint.inbox = inbox;
+                server = Some(endpoint);
+            }
+        } else if let Some(server) = &mut server {
+            to_server.deliver(&mut outbox, &mut server.inbox);
+        }
+        if let Some(server) = &mut server {
+            server.step(now, &mut outbox);
+            to_client.deliver(&mut outbox, &mut client.inbox);
+        }
+
+        let endpoints = std::iter::once(&mut client).chain(server.as_mut());
+        let pending: Vec<&mut Endpoint> = endpoints.filter(|e| e.result.is_none()).collect();
+        if pending.is_empty() {
+            break;
+        }
+        if pending.iter().any(|e| !e.inbox.is_empty()) {
+            continue;
+        }
+        // 双方都在等待，时钟前进到最早的超时
+        let next = pending.iter().filter_map(|e| e.deadline).min().unwrap();
+        now = now.max(next);
+        for endpoint in pending {
+            if endpoint.deadline.is_some_and(|d| d <= now) {
+                endpoint.deadline = None;
+                endpoint.transfer.on_timeout();
+            }
+        }
+    }
+    (client, server)
+}
+
+fn bytes(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+}
+
+#[test]
+fn get_and_put_over_lossy_link() {
+    let sizes = [0, 511, 512, 100_000];
+    let modes = [
+        (1, false, false),
+        (8, false, false),
+        (1, true, false),
+        (8, false, true),
+    ];
+    for len in sizes {
+        for (windowsize, gbn, selective) in modes {
+            for drop_every in [None, Some(7), Some(5)] {
+                let config = TransferConfig {
+                    retry: 10,
+                    gbn,
+                    selective,
+                    ..Default::default()
+                };
+                let data = bytes(len);
+                let case = format!(
+                    "len={len} window={windowsize} gbn={gbn} selective={selective} drop={drop_every:?}"
+                );
+
+                let get = ClientTransfer::get("a.bin", 512, windowsize, config.clone());
+                let (client, _) = run(get, Vec::new(), data.clone(), &config, drop_every);
+                assert_eq!(client.result, Some(Ok(())), "get {case}");
+                assert!(client.written == data, "get {case}");
+
+                let put =
+                    ClientTransfer::put("a.bin", 512, windowsize, Some(len as u64), config.clone());
+                let (client, server) = run(put, data.clone(), Vec::new(), &config, drop_every);
+                assert_eq!(client.result, Some(Ok(())), "put {case}");
+                let server = server.unwrap();
+                assert_eq!(server.result, Some(Ok(())), "put {case}");
+                assert!(server.written == data, "put {case}");
+            }
+        }
+    }
+}
+
+// 双方收到的每个报文前都插入无法解析的报文
+#[test]
+fn malformed_datagrams_are_ignored() {
+    let config = TransferConfig::default();
+    let now = Instant::now();
+    let data = bytes(2000);
+    let garbage: [&[u8]; 3] = [b"\x00", b"\x00\x09junk", b"\x00\x05\x00\x01msg"];
+    let mut outbox = Vec::new();
+    let get = ClientTransfer::get("a.bin", 512, 1, config.clone());
+    let mut client = Endpoint::new(Box::new(get), Vec::new());
+    client.step(now, &mut outbox);
+    let request = Request::parse(&outbox.remove(0)).unwrap();
+    let read = ServerTransfer::read(request.options, Some(data.len() as u64), config);
+    let mut server = Endpoint::new(Box::new(read), data.clone());
+    for _ in 0..100 {
+        server.step(now, &mut outbox);
+        for datagram in outbox.drain(..) {
+            client.inbox.extend(garbage.map(<[u8]>::to_vec));
+            client.inbox.push_back(datagram);
+        }
+        client.step(now, &mut outbox);
+        for datagram in outbox.drain(..) {
+            server.inbox.extend(garbage.map(<[u8]>::to_vec));
+            server.inbox.push_back(datagram);
+        }
+        if client.result.is_some() && server.result.is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.result, Some(Ok(())));
+    assert_eq!(server.result, Some(Ok(())));
+    assert!(client.written == data);
+}
+
+// 截断成只剩报头的 OACK 不能让客户端按默认参数开始传输
+#[test]
+fn empty_oack_is_ignored() {
+    let now = Instant::now();
+    let mut put = ClientTransfer::put("a.bin", 1024, 1, Some(2000), TransferConfig::default());
+    assert!(matches!(put.poll(now), Output::Transmit(_)));
+    assert!(matches!(put.poll(now), Output::Wait(_)));
+    put.on_datagram(&[0, 6], now);
+    assert!(matches!(put.poll(now), Output::Wait(_)));
+
+    let oack = TftpPacket::OACK(TftpOptions::from_iter([("blksize", "1024")]));
+    put.on_datagram(&oack.serialize(), now);
+    assert_eq!(put.poll(now), Output::Read(1024));
+}
+
+// 服务端超时重发的 OACK 在客户端开始发送数据后才到达
+#[test]
+fn duplicate_oack_is_ignored() {
+    let config = TransferConfig::default();
+    let now = Instant::now();
+    let data = bytes(2000);
+    let mut outbox = Vec::new();
+    let put = ClientTransfer::put("a.bin", 512, 1, Some(data.len() as u64), config.clone());
+    let mut client = Endpoint::new(Box::new(put), data.clone());
+    client.step(now, &mut outbox);
+    let request = Request::parse(&outbox.remove(0)).unwrap();
+    let write = ServerTransfer::write(request.options, config);
+    let mut server = Endpoint::new(Box::new(write), Vec::new());
+    server.step(now, &mut outbox);
+    let oack = outbox.remove(0);
+    client.inbox.extend([oack.clone(), oack]);
+    for _ in 0..100 {
+        client.step(now, &mut outbox);
+        server.inbox.extend(outbox.drain(..));
+        server.step(now, &mut outbox);
+        client.inbox.extend(outbox.drain(..));
+        if client.result.is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.result, Some(Ok(())));
+    assert!(server.written == data);
+}
+
+// 上传最后一块的 ACK 丢失时，客户端重传最后一块，服务端仍在等待并再次确认
+#[test]
+fn lost_final_ack_is_recovered() {
+    let config = TransferConfig::default();
+    let mut now = Instant::now();
+    let data = bytes(1000);
+    let mut outbox = Vec::new();
+    let put = ClientTransfer::put("a.bin", 512, 1, Some(data.len() as u64), config.clone());
+    let mut client = Endpoint::new(Box::new(put), data.clone());
+    client.step(now, &mut outbox);
+    let request = Request::parse(&outbox.remove(0)).unwrap();
+    let write = ServerTransfer::write(request.options, config);
+    let mut server = Endpoint::new(Box::new(write), Vec::new());
+    let mut dropped = false;
+    for _ in 0..100 {
+        server.step(now, &mut outbox);
+        for datagram in outbox.drain(..) {
+            if !dropped && datagram == [0, 4, 0, 2] {
+                dropped = true;
+                continue;
+            }
+            client.inbox.push_back(datagram);
+        }
+        client.step(now, &mut outbox);
+        server.inbox.extend(outbox.drain(..));
+        if client.result.is_some() && server.result.is_some() {
+            break;
+        }
+        if client.inbox.is_empty() && server.inbox.is_empty() {
+            // 双方都在等待，时钟前进到最早的超时
+            let mut pending = [&mut client, &mut server];
+            let next = pending.iter().filter_map(|e| e.deadline).min().unwrap();
+            now = now.max(next);
+            for endpoint in pending.iter_mut() {
+                if endpoint.result.is_none() && endpoint.deadline.is_some_and(|d| d <= now) {
+                    endpoint.deadline = None;
+                    endpoint.transfer.on_timeout();
+                }
+            }
+        }
+    }
+    assert!(dropped);
+    assert_eq!(client.result, Some(Ok(())));
+    assert_eq!(server.result, Some(Ok(())));
+    assert!(server.written == data);
+}
+
+// 上传的存储在最后的 ACK 之前提交，提交失败时对端收到 ERROR
+#[test]
+fn commit_precedes_final_ack() {
+    let now = Instant::now();
+    let options = TftpOptions::new();
+    let mut server = ServerTransfer::write(options.clone(), TransferConfig::default());
+    assert_eq!(server.poll(now), Output::Transmit(&[0, 4, 0, 0]));
+    assert!(matches!(server.poll(now), Output::Wait(_)));
+    let last = TftpPacket::DATA {
+        block: 1,
+        data: b"short".to_vec(),
+    };
+    server.on_datagram(&last.serialize(), now);
+    assert_eq!(server.poll(now), Output::Write(b"short"));
+    assert_eq!(server.poll(now), Output::Commit);
+    assert_eq!(server.poll(now), Output::Transmit(&[0, 4, 0, 1]));
+
+    let mut server = ServerTransfer::write(options, TransferConfig::default());
+    server.poll(now);
+    server.poll(now);
+    server.on_datagram(&last.serialize(), now);
+    assert_eq!(server.poll(now), Output::Write(b"short"));
+    assert_eq!(server.poll(now), Output::Commit);
+    server.abort(ERR_ACCESS_VIOLATION, "Checksum mismatch".to_string());
+    let Output::Transmit(error) = server.poll(now) else {
+        panic!("expect ERROR");
+    };
+    assert_eq!(
+        TftpPacket::deserialize(error).unwrap(),
+        TftpPacket::ERROR {
+            code: ERR_ACCESS_VIOLATION,
+            msg: "Checksum mismatch".to_string()
+        }
+    );
+    assert_eq!(server.poll(now), Output::Failed("Checksum mismatch"));
+}
+
+#[test]
+fn stats_count_blocks_and_retransmits() {
+    let config = TransferConfig {
+        retry: 10,
+        ..Default::default()
+    };
+    let data = bytes(100_000);
+    let blocks = data.len().div_ceil(512) as u64;
+
+    let get = ClientTransfer::get("a.bin", 512, 8, config.clone());
+    let (client, server) = run(get, Vec::new(), data.clone(), &config, None);
+    let server = server.unwrap().transfer.stats();
+    for stats in [client.transfer.stats(), server.clone()] {
+        assert_eq!(stats.bytes, data.len() as u64);
+        assert_eq!(stats.blocks, blocks);
+        assert_eq!((stats.blksize, stats.windowsize), (512, 8));
+        assert_eq!((stats.retransmits, stats.timeouts), (0, 0));
+        assert!(stats.rtt.is_some());
+    }
+    assert_eq!(server.duplicate_acks, 0);
+
+    // 丢包时发送端重传数据块，丢失的 ACK 表现为超时或重复 ACK
+    let get = ClientTransfer::get("a.bin", 512, 8, config.clone());
+    let (client, server) = run(get, Vec::new(), data.clone(), &config, Some(5));
+    assert!(client.written == data);
+    assert_eq!(client.transfer.stats().blocks, blocks);
+    // 发送端只计入已确认的块，最后的 ACK 全部丢失时会少于总块数
+    let server = server.unwrap().transfer.stats();
+    assert!(server.blocks <= blocks, "{server:?}");
+    assert_eq!(server.bytes, (server.blocks * 512).min(data.len() as u64));
+    assert!(server.retransmits > 0, "{server:?}");
+    assert!(server.timeouts + server.duplicate_acks > 0, "{server:?}");
+}
+
+// 记录调用的自定义拥塞控制，窗口固定为 2
+struct Recording {
+    acked: Arc<AtomicU64>,
+}
+
+impl CongestionControl for Recording {
+    fn window(&self) -> u16 {
+        2
+    }
+
+    fn on_ack(&mut self, acked: u16) {
+        self.acked.fetch_add(u64::from(acked), Ordering::Relaxed);
+    }
+
+    fn on_loss(&mut self) {}
+
+    fn on_timeout(&mut self) {}
+}
+
+#[test]
+fn custom_congestion_control_is_used() {
+    let acked = Arc::new(AtomicU64::new(0));
+    let built = Arc::new(Mutex::new(Vec::new()));
+    let config = TransferConfig {
+        congestion: Congestion::custom({
+            let acked = acked.clone();
+            let built = built.clone();
+            move |max| {
+                built.lock().unwrap().push(max);
+                Box::new(Recording {
+                    acked: acked.clone(),
+                })
+            }
+        }),
+        ..Default::default()
+    };
+    let data = bytes(10_000);
+    let blocks = data.len().div_ceil(512) as u64;
+    let get = ClientTransfer::get("a.bin", 512, 8, config.clone());
+    let (client, server) = run(get, Vec::new(), data.clone(), &config, None);
+    assert!(client.written == data);
+    assert_eq!(server.unwrap().transfer.stats().blocks, blocks);
+    assert_eq!(*built.lock().unwrap(), [8]);
+    // 最后的 ACK 直接结束传输，它确认的块（不超过一个窗口）不再交给拥塞控制
+    let acked = acked.load(Ordering::Relaxed);
+    assert!((blocks - 2..blocks).contains(&acked), "{acked}");
+}
+
+#[test]
+fn get_reports_server_filesize() {
+    let config = TransferConfig::default();
+    let mut get = ClientTransfer::get("a.bin", 1024, 4, config.clone());
+    let request = match get.poll(Instant::now()) {
+        Output::Transmit(datagram) => Request::parse(datagram).unwrap(),
+        output => panic!("unexpected {output:?}"),
+    };
+    assert!(!request.write);
+    assert_eq!(request.filename, "a.bin");
+    assert_eq!(request.options.blksize(), Ok(Some(1024)));
+
+    let mut server = ServerTransfer::read(request.options, Some(4321), config);
+    let Output::Transmit(oack) = server.poll(Instant::now()) else {
+        panic!("expect OACK");
+    };
+    get.on_datagram(oack, Instant::now());
+    assert_eq!(get.filesize(), Some(4321));
+}
+
+#[test]
+fn invalid_option_is_rejected() {
+    let config = TransferConfig::default();
+    let options = TftpOptions::from_iter([("windowsize", "0")]);
+    let mut server = ServerTransfer::read(options, Some(10), config.clone());
+    let Output::Transmit(error) = server.poll(Instant::now()) else {
+        panic!("expect ERROR");
+    };
+    let error = error.to_vec();
+    assert_eq!(
+        server.poll(Instant::now()),
+        Output::Failed("Invalid windowsize: 0")
+    );
+
+    let mut get = ClientTransfer::get("a.bin", 512, 1, config);
+    get.poll(Instant::now());
+    get.on_datagram(&error, Instant::now());
+    assert_eq!(
+        get.poll(Instant::now()),
+        Output::Failed("Server error: code=8, msg=Invalid windowsize: 0")
+    );
+}
+
+#[test]
+fn invalid_oack_is_rejected() {
+    let mut get = ClientTransfer::get("a.bin", 1024, 1, TransferConfig::default());
+    get.poll(Instant::now());
+    let oack = TftpPacket::OACK(TftpOptions::from_iter([("blksize", "0")]));
+    get.on_datagram(&oack.serialize(), Instant::now());
+    let Output::Transmit(error) = get.poll(Instant::now()) else {
+        panic!("expect ERROR");
+    };
+    assert_eq!(
+        TftpPacket::deserialize(error).unwrap(),
+        TftpPacket::ERROR {
+            code: ERR_OPTION,
+            msg: "Invalid blksize: 0".to_string()
+        }
+    );
+    assert_eq!(
+        get.poll(Instant::now()),
+        Output::Failed("Invalid blksize: 0")
+    );
+}
+
+// RFC 2347：选项名称不区分大小写，OACK 按固定顺序回应
+#[test]
+fn uppercase_options_are_negotiated() {
+    let config = TransferConfig::default();
+    let options =
+        TftpOptions::from_iter([("TSIZE", "0"), ("WindowSize", "4"), ("BLKSIZE", "1024")]);
+    let mut server = ServerTransfer::read(options, Some(4321), config.clone());
+    let Output::Transmit(oack) = server.poll(Instant::now()) else {
+        panic!("expect OACK");
+    };
+    let oack = oack.to_vec();
+    let TftpPacket::OACK(options) = TftpPacket::deserialize(&oack).unwrap() else {
+        panic!("expect OACK");
+    };
+    assert_eq!(
+        options.iter().collect::<Vec<_>>(),
+        [("blksize", "1024"), ("windowsize", "4"), ("tsize", "4321")]
+    );
+
+    let mut get = ClientTransfer::get("a.bin", 1024, 4, config);
+    get.poll(Instant::now());
+    let oack = TftpPacket::OACK(TftpOptions::from_iter([
+        ("BLKSIZE", "1024"),
+        ("TSize", "4321"),
+    ]));
+    get.on_datagram(&oack.serialize(), Instant::now());
+    assert_eq!(get.filesize(), Some(4321));
+}
+
+// 发送端缓存整个窗口，对端请求的窗口按字节预算缩小
+#[test]
+fn large_window_is_clamped() {
+    let config = TransferConfig::default();
+    let options = TftpOptions::from_iter([("blksize", "65464"), ("windowsize", "65535")]);
+    let mut server = ServerTransfer::read(options, Some(1 << 30), config.clone());
+    let oack = server.negotiated().unwrap();
+    let windowsize = (config.window_budget / 65464) as u16;
+    assert_eq!(oack.windowsize(), Ok(Some(windowsize)));
+    assert_eq!(server.stats().windowsize, windowsize);
+
+    // 预算不足一块时仍允许窗口为 1
+    let config = TransferConfig {
+        window_budget: 100,
+        ..Default::default()
+    };
+    let options = TftpOptions::from_iter([("blksize", "1024"), ("windowsize", "16")]);
+    let mut server = ServerTransfer::write(options, config.clone());
+    assert_eq!(server.negotiated().unwrap().windowsize(), Ok(Some(1)));
+
+    // 客户端按 OACK 中缩小后的窗口传输
+    let data = bytes(20_000);
+    let get = ClientTransfer::get("a.bin", 1024, 16, config.clone());
+    let (client, server) = run(get, Vec::new(), data.clone(), &config, None);
+    assert!(client.written == data);
+    assert_eq!(client.transfer.stats().windowsize, 1);
+    assert_eq!(server.unwrap().transfer.stats().windowsize, 1);
+}
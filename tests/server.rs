@@ -0,0 +1,1171 @@
+mod common;
+
+use common::test_dir;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tftp::{
+    Cancelled, CommandHooks, Content, EventKind, OptionAction, PortRange, Progress, RequestContext,
+    SessionConfig, TftpClient, TftpOptions, TftpPacket, TransferEvent, TransferHandle, Upload,
+};
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+fn request(opcode: u8, filename: &str) -> Vec<u8> {
+    let mut pkt = vec![0, opcode];
+    pkt.extend_from_slice(filename.as_bytes());
+    pkt.push(0);
+    pkt.extend_from_slice(b"octet\0");
+    pkt
+}
+
+fn rrq(filename: &str) -> Vec<u8> {
+    request(1, filename)
+}
+
+fn wrq(filename: &str) -> Vec<u8> {
+    request(2, filename)
+}
+
+// 像客户端一样超时重发请求，直到收到回应
+async fn request_until_reply(
+    socket: &UdpSocket,
+    request: &[u8],
+    addr: SocketAddr,
+) -> (TftpPacket, SocketAddr) {
+    let mut buf = [0u8; 1024];
+    for _ in 0..20 {
+        socket.send_to(request, addr).await.unwrap();
+        if let Ok(reply) = timeout(Duration::from_millis(100), socket.recv_from(&mut buf)).await {
+            let (len, from) = reply.unwrap();
+            return (TftpPacket::deserialize(&buf[..len]).unwrap(), from);
+        }
+    }
+    panic!("no reply to {request:?}");
+}
+
+#[tokio::test]
+async fn duplicate_rrq_is_served_by_one_session() {
+    let dir = test_dir("dup-rrq");
+    std::fs::write(dir.path().join("dup.bin"), vec![0x5a; 1000]).unwrap();
+    let config = SessionConfig {
+        directory: dir.path().to_path_buf(),
+        timeout: 200,
+        retry: 2,
+        ..Default::default()
+    };
+    let server = common::server(config);
+    let mut events = server.subscribe();
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    // 模拟客户端超时重发 RRQ，两次请求都不确认，原会话重传失败后结束
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(&rrq("dup.bin"), addr).await.unwrap();
+    client.send_to(&rrq("dup.bin"), addr).await.unwrap();
+    let mut requests = 0;
+    loop {
+        match next_event(&mut events).await.kind {
+            EventKind::RequestReceived => requests += 1,
+            EventKind::Failed { .. } => break,
+            _ => (),
+        }
+    }
+    assert_eq!(requests, 1);
+    let mut senders = HashSet::new();
+    let mut buf = [0u8; 1024];
+    while let Ok((_, from)) = client.try_recv_from(&mut buf) {
+        senders.insert(from);
+    }
+    assert_eq!(senders.len(), 1, "expected one TID, got {senders:?}");
+
+    // 记录已被清理，同样的请求可以重新被处理
+    let first = senders.into_iter().next().unwrap();
+    let (_, tid) = request_until_reply(&client, &rrq("dup.bin"), addr).await;
+    assert_ne!(tid, first);
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 服务端发出最后的 ACK 后还会等待重传，期间同样的 WRQ 按新请求处理
+#[tokio::test]
+async fn wrq_is_accepted_again_while_server_dallies() {
+    let dir = test_dir("dally-wrq");
+    let server = common::server(SessionConfig {
+        directory: dir.path().to_path_buf(),
+        timeout: 5000,
+        ..Default::default()
+    });
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let data = |data: &[u8]| {
+        TftpPacket::DATA {
+            block: 1,
+            data: data.to_vec(),
+        }
+        .serialize()
+    };
+    client.send_to(&wrq("again.bin"), addr).await.unwrap();
+    let (ack, first) = recv_packet(&client).await;
+    assert_eq!(ack, TftpPacket::ACK(0));
+    client.send_to(&data(b"first"), first).await.unwrap();
+    assert_eq!(recv_packet(&client).await, (TftpPacket::ACK(1), first));
+
+    // 原会话要等待 10 秒才结束
+    let (ack, tid) = request_until_reply(&client, &wrq("again.bin"), addr).await;
+    assert_eq!(ack, TftpPacket::ACK(0));
+    assert_ne!(tid, first);
+    client.send_to(&data(b"second"), tid).await.unwrap();
+    assert_eq!(recv_packet(&client).await, (TftpPacket::ACK(1), tid));
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+async fn recv_packet(socket: &UdpSocket) -> (TftpPacket, SocketAddr) {
+    let mut buf = [0u8; 1024];
+    let (len, from) = timeout(Duration::from_secs(1), socket.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    (TftpPacket::deserialize(&buf[..len]).unwrap(), from)
+}
+
+// 按 hwaddr 选择文件，build-id 在 OACK 中确认或被拒绝
+#[tokio::test]
+async fn vendor_options_are_handled() {
+    let dir = test_dir("vendor");
+    std::fs::create_dir_all(dir.path().join("aa-bb")).unwrap();
+    std::fs::write(dir.path().join("boot.bin"), b"generic").unwrap();
+    std::fs::write(dir.path().join("aa-bb/boot.bin"), b"board").unwrap();
+    let config = SessionConfig {
+        directory: dir.path().to_path_buf(),
+        ..Default::default()
+    };
+    let server = common::server(config)
+        .with_option_handler(
+            "hwaddr",
+            |_: &str, value: &str, ctx: &mut RequestContext| {
+                ctx.filename = format!("{value}/{}", ctx.filename);
+                OptionAction::Ignore
+            },
+        )
+        .with_option_handler(
+            "build-id",
+            |_: &str, value: &str, _: &mut RequestContext| match value {
+                "bad" => OptionAction::Reject("Unsupported build".to_string()),
+                _ => OptionAction::Accept(value.to_string()),
+            },
+        );
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let request = |options: TftpOptions| {
+        TftpPacket::RRQ {
+            filename: "boot.bin".to_string(),
+            mode: "octet".to_string(),
+            options,
+        }
+        .serialize()
+    };
+    let options = TftpOptions::from_iter([("HWADDR", "aa-bb"), ("build-id", "42"), ("x", "1")]);
+    client.send_to(&request(options), addr).await.unwrap();
+    let (oack, tid) = recv_packet(&client).await;
+    assert_eq!(
+        oack,
+        TftpPacket::OACK(TftpOptions::from_iter([("build-id", "42")]))
+    );
+    client
+        .send_to(&TftpPacket::ACK(0).serialize(), tid)
+        .await
+        .unwrap();
+    let (data, _) = recv_packet(&client).await;
+    assert_eq!(
+        data,
+        TftpPacket::DATA {
+            block: 1,
+            data: b"board".to_vec()
+        }
+    );
+    client
+        .send_to(&TftpPacket::ACK(1).serialize(), tid)
+        .await
+        .unwrap();
+
+    let options = TftpOptions::from_iter([("build-id", "bad")]);
+    client.send_to(&request(options), addr).await.unwrap();
+    let (error, _) = recv_packet(&client).await;
+    assert_eq!(
+        error,
+        TftpPacket::ERROR {
+            code: 8,
+            msg: "Unsupported build".to_string()
+        }
+    );
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 按对端生成配置文件，其余文件仍从服务目录读取
+#[tokio::test]
+async fn content_provider_generates_files() {
+    let server_dir = test_dir("provider-server");
+    let client_dir = test_dir("provider-client");
+    std::fs::create_dir_all(client_dir.path().join("pxelinux.cfg")).unwrap();
+    std::fs::write(server_dir.path().join("plain.bin"), b"from disk").unwrap();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        ..Default::default()
+    })
+    .with_content_provider(|ctx: &RequestContext| {
+        Ok(match ctx.filename.as_str() {
+            "pxelinux.cfg/01-aa-bb" => Some(format!("menu for {}\n", ctx.peer.ip()).into()),
+            "stream.bin" => Some(Content::new(&b"unsized"[..])),
+            _ => None,
+        })
+    });
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..Default::default()
+        },
+        512,
+        1,
+    );
+    for file in ["pxelinux.cfg/01-aa-bb", "plain.bin"] {
+        client.get_file(addr, file.to_string()).await.unwrap();
+    }
+    assert_eq!(
+        std::fs::read(client_dir.path().join("pxelinux.cfg/01-aa-bb")).unwrap(),
+        b"menu for 127.0.0.1\n"
+    );
+    assert_eq!(
+        std::fs::read(client_dir.path().join("plain.bin")).unwrap(),
+        b"from disk"
+    );
+
+    // 大小未知时不回应 tsize，直接发送数据
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let rrq = TftpPacket::RRQ {
+        filename: "stream.bin".to_string(),
+        mode: "octet".to_string(),
+        options: TftpOptions::from_iter([("tsize", "0")]),
+    };
+    socket.send_to(&rrq.serialize(), addr).await.unwrap();
+    let (data, tid) = recv_packet(&socket).await;
+    assert_eq!(
+        data,
+        TftpPacket::DATA {
+            block: 1,
+            data: b"unsized".to_vec()
+        }
+    );
+    socket
+        .send_to(&TftpPacket::ACK(1).serialize(), tid)
+        .await
+        .unwrap();
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 在内存中收集上传内容，记录提交和中止的文件
+#[derive(Clone, Default)]
+struct Uploads {
+    committed: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    aborted: Arc<Mutex<Vec<String>>>,
+}
+
+struct MemoryUpload {
+    name: String,
+    data: Vec<u8>,
+    uploads: Uploads,
+}
+
+impl Upload for MemoryUpload {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.windows(6).any(|w| w == b"secret") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Secrets are not allowed",
+            ));
+        }
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    // 提交时校验内容，失败时客户端收到 ERROR
+    fn commit(self: Box<Self>) -> io::Result<()> {
+        if self.data.starts_with(b"corrupt") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Checksum mismatch",
+            ));
+        }
+        let mut committed = self.uploads.committed.lock().unwrap();
+        committed.insert(self.name.clone(), self.data.clone());
+        Ok(())
+    }
+
+    fn abort(self: Box<Self>) {
+        self.uploads.aborted.lock().unwrap().push(self.name);
+    }
+}
+
+// .cfg 文件交给上传接收者，其余文件写入服务目录
+#[tokio::test]
+async fn upload_sink_receives_files() {
+    let server_dir = test_dir("sink-server");
+    let client_dir = test_dir("sink-client");
+    let backup = vec![0x42; 3000];
+    std::fs::write(client_dir.path().join("backup.cfg"), &backup).unwrap();
+    std::fs::write(client_dir.path().join("leak.cfg"), b"password: secret").unwrap();
+    std::fs::write(client_dir.path().join("disk.bin"), b"to disk").unwrap();
+    std::fs::write(client_dir.path().join("bad.cfg"), b"corrupt backup").unwrap();
+    let config = SessionConfig {
+        timeout: 100,
+        ..Default::default()
+    };
+    let uploads = Uploads::default();
+    let sink_uploads = uploads.clone();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        ..config.clone()
+    })
+    .with_drain_timeout(Duration::from_secs(5))
+    .with_upload_sink(move |ctx: &RequestContext| {
+        if !ctx.filename.ends_with(".cfg") {
+            return Ok(None);
+        }
+        let upload = MemoryUpload {
+            name: ctx.filename.clone(),
+            data: Vec::new(),
+            uploads: sink_uploads.clone(),
+        };
+        Ok(Some(Box::new(upload) as Box<dyn Upload>))
+    });
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..config
+        },
+        512,
+        4,
+    );
+    client
+        .put_file(addr, "backup.cfg".to_string())
+        .await
+        .unwrap();
+    // 最后的 ACK 在提交之后发出，客户端成功时内容已经提交
+    assert!(uploads.committed.lock().unwrap().contains_key("backup.cfg"));
+    client.put_file(addr, "disk.bin".to_string()).await.unwrap();
+    let error = client
+        .put_file(addr, "leak.cfg".to_string())
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("Secrets are not allowed"),
+        "{error}"
+    );
+    // 提交失败时客户端收到 ERROR 而不是最后的 ACK
+    let error = client
+        .put_file(addr, "bad.cfg".to_string())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("Checksum mismatch"), "{error}");
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+    assert_eq!(
+        *uploads.committed.lock().unwrap(),
+        HashMap::from([("backup.cfg".to_string(), backup)])
+    );
+    assert_eq!(*uploads.aborted.lock().unwrap(), ["leak.cfg"]);
+    assert!(!server_dir.path().join("backup.cfg").exists());
+    assert_eq!(
+        std::fs::read(server_dir.path().join("disk.bin")).unwrap(),
+        b"to disk"
+    );
+}
+
+async fn next_event(events: &mut broadcast::Receiver<TransferEvent>) -> TransferEvent {
+    timeout(Duration::from_secs(2), events.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn transfer_events_are_published() {
+    let server_dir = test_dir("events-server");
+    let client_dir = test_dir("events-client");
+    let data = vec![0x33; 100_000];
+    std::fs::write(server_dir.path().join("image.bin"), &data).unwrap();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+    let mut events = server.subscribe();
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..Default::default()
+        },
+        1468,
+        4,
+    );
+    client
+        .get_file(addr, "image.bin".to_string())
+        .await
+        .unwrap();
+    let event = next_event(&mut events).await;
+    assert_eq!(event.filename, "image.bin");
+    assert!(!event.write);
+    assert!(matches!(event.kind, EventKind::RequestReceived));
+    let EventKind::Negotiated(options) = next_event(&mut events).await.kind else {
+        panic!("expect Negotiated");
+    };
+    assert_eq!(options.blksize(), Ok(Some(1468)));
+    assert_eq!(options.windowsize(), Ok(Some(4)));
+    let stats = loop {
+        match next_event(&mut events).await.kind {
+            EventKind::Progress { bytes, .. } => assert!(bytes <= data.len() as u64),
+            EventKind::Completed(stats) => break stats,
+            kind => panic!("unexpected {kind:?}"),
+        }
+    };
+    assert_eq!(stats.bytes, data.len() as u64);
+    assert_eq!(stats.blocks, data.len().div_ceil(1468) as u64);
+
+    client
+        .get_file(addr, "missing.bin".to_string())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        next_event(&mut events).await.kind,
+        EventKind::RequestReceived
+    ));
+    let EventKind::Failed { code, reason } = next_event(&mut events).await.kind else {
+        panic!("expect Failed");
+    };
+    assert_eq!(code, 1, "{reason}");
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 上传完成后执行脚本，脚本把收到的环境变量写入文件
+#[cfg(unix)]
+#[tokio::test]
+async fn upload_hook_runs_command() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server_dir = test_dir("hook-server");
+    let client_dir = test_dir("hook-client");
+    let script_dir = test_dir("hook-script");
+    let script = script_dir.path().join("on-upload.sh");
+    let output = script.with_extension("out");
+    std::fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\nenv | grep ^TFTP_ | sort > {}\n",
+            output.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::write(client_dir.path().join("backup.cfg"), vec![b'x'; 1234]).unwrap();
+
+    let config = SessionConfig {
+        timeout: 100,
+        ..Default::default()
+    };
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        ..config.clone()
+    })
+    .with_drain_timeout(Duration::from_secs(5));
+    let hooks = CommandHooks::new()
+        .with_upload_command(&script)
+        .with_timeout(Duration::from_secs(5));
+    let hooks = tokio::spawn(hooks.run(server.subscribe_outcomes()));
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..config
+        },
+        512,
+        1,
+    );
+    client
+        .put_file(addr, "backup.cfg".to_string())
+        .await
+        .unwrap();
+    client
+        .get_file(addr, "backup.cfg".to_string())
+        .await
+        .unwrap();
+
+    // 服务端结束后事件通道关闭，hooks 等待命令执行完才返回
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+    timeout(Duration::from_secs(5), hooks)
+        .await
+        .unwrap()
+        .unwrap();
+    let env = std::fs::read_to_string(&output).unwrap();
+    let path = std::path::absolute(server_dir.path().join("backup.cfg")).unwrap();
+    for line in [
+        "TFTP_FILENAME=backup.cfg".to_string(),
+        "TFTP_MODE=octet".to_string(),
+        "TFTP_PEER_IP=127.0.0.1".to_string(),
+        format!("TFTP_PATH={}", path.display()),
+        "TFTP_SIZE=1234".to_string(),
+    ] {
+        assert!(env.lines().any(|l| l == line), "{line} not in {env}");
+    }
+    assert!(env.contains("TFTP_DURATION="), "{env}");
+}
+
+#[tokio::test]
+async fn client_reports_progress() {
+    let server_dir = test_dir("progress-server");
+    let client_dir = test_dir("progress-client");
+    std::fs::write(server_dir.path().join("down.bin"), vec![0x11; 300_000]).unwrap();
+    std::fs::write(client_dir.path().join("up.bin"), vec![0x22; 200_000]).unwrap();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..Default::default()
+        },
+        512,
+        4,
+    );
+    // 同一客户端同时进行的传输各自报告进度
+    let (down, up) = (TransferHandle::new(), TransferHandle::new());
+    let mut down_progress = down.progress();
+    let mut up_progress = up.progress();
+    assert_eq!(*down_progress.borrow_and_update(), Progress::default());
+    let (got, put) = tokio::join!(
+        client.get_file_with(addr, "down.bin".to_string(), &down),
+        client.put_file_with(addr, "up.bin".to_string(), &up),
+    );
+    got.unwrap();
+    put.unwrap();
+
+    // 下载的总大小来自服务端的 tsize
+    assert!(down_progress.has_changed().unwrap());
+    assert_eq!(
+        *down_progress.borrow_and_update(),
+        Progress {
+            bytes: 300_000,
+            total: Some(300_000)
+        }
+    );
+    // 上传的总大小为本地文件大小
+    assert_eq!(
+        *up_progress.borrow_and_update(),
+        Progress {
+            bytes: 200_000,
+            total: Some(200_000)
+        }
+    );
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 本次传输收到数据后取消
+fn cancel_after_progress() -> TransferHandle {
+    let handle = TransferHandle::new();
+    let canceller = handle.clone();
+    let mut progress = handle.progress();
+    tokio::spawn(async move {
+        while progress.changed().await.is_ok() {
+            if progress.borrow().bytes > 0 {
+                canceller.cancel();
+                break;
+            }
+        }
+    });
+    handle
+}
+
+#[tokio::test]
+async fn cancelled_get_notifies_server() {
+    let server_dir = test_dir("cancel-server");
+    let client_dir = test_dir("cancel-client");
+    std::fs::write(server_dir.path().join("slow.bin"), vec![0x44; 500_000]).unwrap();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        rate_limit: Some(100_000),
+        ..Default::default()
+    });
+    let mut events = server.subscribe();
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..Default::default()
+        },
+        512,
+        1,
+    );
+    let cancel = cancel_after_progress();
+    let error = client
+        .get_file_with(addr, "slow.bin".to_string(), &cancel)
+        .await
+        .unwrap_err();
+    assert!(error.is::<Cancelled>(), "{error}");
+    // 默认删除未完成的文件
+    assert!(!client_dir.path().join("slow.bin").exists());
+
+    // 服务端收到 ERROR 后结束会话
+    loop {
+        let event = next_event(&mut events).await;
+        match event.kind {
+            EventKind::Failed { reason, .. } => {
+                assert!(reason.contains("Transfer cancelled"), "{reason}");
+                break;
+            }
+            EventKind::Completed(_) => panic!("transfer should not complete"),
+            _ => (),
+        }
+    }
+
+    // 保留已收到的部分
+    let client = client.with_keep_partial(true);
+    let cancel = cancel_after_progress();
+    let error = client
+        .get_file_with(addr, "slow.bin".to_string(), &cancel)
+        .await
+        .unwrap_err();
+    assert!(error.is::<Cancelled>(), "{error}");
+    let partial = std::fs::metadata(client_dir.path().join("slow.bin"))
+        .unwrap()
+        .len();
+    assert!(partial > 0 && partial < 500_000, "{partial}");
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 窗口传输不限速时，取消前已排队的写入不能重新创建已删除的文件
+#[tokio::test(flavor = "multi_thread")]
+async fn cancelled_windowed_get_leaves_no_file() {
+    let server_dir = test_dir("cancel-window-server");
+    let client_dir = test_dir("cancel-window-client");
+    let image: Vec<u8> = (0..16_000_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(server_dir.path().join("big.bin"), &image).unwrap();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..Default::default()
+        },
+        1428,
+        16,
+    );
+    // 取消时机不同，写入和取消在不同线程上交错
+    for delay in (10..60).step_by(5) {
+        let cancel = CancellationToken::new();
+        let token = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            token.cancel();
+        });
+        let error = client
+            .get_file_cancellable(addr, "big.bin".to_string(), cancel)
+            .await
+            .unwrap_err();
+        assert!(error.is::<Cancelled>(), "{error}");
+        assert!(!client_dir.path().join("big.bin").exists());
+    }
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn client_streams_without_local_files() {
+    let server_dir = test_dir("stream-server");
+    let client_dir = test_dir("stream-client");
+    let firmware: Vec<u8> = (0..150_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(server_dir.path().join("firmware.bin"), &firmware).unwrap();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    // 客户端目录不存在，所有数据都在内存中
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().join("missing"),
+            ..Default::default()
+        },
+        512,
+        1,
+    );
+    let mut received = Vec::new();
+    let stats = client
+        .get_to_writer(addr, "firmware.bin".to_string(), &mut received)
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes, firmware.len() as u64);
+    assert!(received == firmware);
+
+    let config = b"hostname switch-01\n".repeat(100);
+    let sized = TransferHandle::new();
+    client
+        .put_from_reader_with(
+            addr,
+            "sized.cfg".to_string(),
+            &config[..],
+            Some(config.len() as u64),
+            &sized,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        *sized.progress().borrow(),
+        Progress {
+            bytes: config.len() as u64,
+            total: Some(config.len() as u64),
+        }
+    );
+    // 大小未知时请求中没有选项，服务端以 ACK 0 回应
+    let unknown = TransferHandle::new();
+    client
+        .put_from_reader_with(addr, "unsized.cfg".to_string(), &config[..], None, &unknown)
+        .await
+        .unwrap();
+    assert_eq!(unknown.progress().borrow().total, None);
+    for name in ["sized.cfg", "unsized.cfg"] {
+        assert_eq!(std::fs::read(server_dir.path().join(name)).unwrap(), config);
+    }
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 单端口模式下两个对端同时上传和下载
+#[tokio::test]
+async fn single_port_serves_concurrent_transfers() {
+    let server_dir = test_dir("single-server");
+    let get_dir = test_dir("single-get");
+    let put_dir = test_dir("single-put");
+    let image: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+    let backup: Vec<u8> = (0..150_000u32).map(|i| (i % 241) as u8).collect();
+    std::fs::write(server_dir.path().join("image.bin"), &image).unwrap();
+    std::fs::write(put_dir.path().join("backup.bin"), &backup).unwrap();
+    let config = SessionConfig {
+        timeout: 100,
+        ..Default::default()
+    };
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        ..config.clone()
+    })
+    .with_single_port(true)
+    .with_drain_timeout(Duration::from_secs(5));
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let getter = TftpClient::new(
+        SessionConfig {
+            directory: get_dir.path().to_path_buf(),
+            ..config.clone()
+        },
+        1468,
+        4,
+    );
+    let putter = TftpClient::new(
+        SessionConfig {
+            directory: put_dir.path().to_path_buf(),
+            ..config
+        },
+        512,
+        8,
+    );
+    let (get, put) = tokio::join!(
+        getter.get_file(addr, "image.bin".to_string()),
+        putter.put_file(addr, "backup.bin".to_string()),
+    );
+    get.unwrap();
+    put.unwrap();
+
+    // 等待服务端结束上传会话
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+    assert!(std::fs::read(get_dir.path().join("image.bin")).unwrap() == image);
+    assert!(std::fs::read(server_dir.path().join("backup.bin")).unwrap() == backup);
+}
+
+// 单端口模式下所有回复都来自监听端口，报文按对端地址交给各自的会话
+#[tokio::test]
+async fn single_port_routes_by_peer() {
+    let dir = test_dir("single-route");
+    std::fs::write(dir.path().join("a.bin"), vec![b'a'; 600]).unwrap();
+    std::fs::write(dir.path().join("b.bin"), vec![b'b'; 600]).unwrap();
+    let server = common::server(SessionConfig {
+        directory: dir.path().to_path_buf(),
+        ..Default::default()
+    })
+    .with_single_port(true);
+    let mut events = server.subscribe();
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let data = |block, byte, len| TftpPacket::DATA {
+        block,
+        data: vec![byte; len],
+    };
+    let ack = |block| TftpPacket::ACK(block).serialize();
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    a.send_to(&rrq("a.bin"), addr).await.unwrap();
+    b.send_to(&rrq("b.bin"), addr).await.unwrap();
+    assert_eq!(recv_packet(&a).await, (data(1, b'a', 512), addr));
+    assert_eq!(recv_packet(&b).await, (data(1, b'b', 512), addr));
+
+    // 对端的 ACK 只推进自己的会话，会话进行中的重复请求被丢弃
+    a.send_to(&ack(1), addr).await.unwrap();
+    assert_eq!(recv_packet(&a).await, (data(2, b'a', 88), addr));
+    b.send_to(&rrq("a.bin"), addr).await.unwrap();
+    b.send_to(&ack(1), addr).await.unwrap();
+    assert_eq!(recv_packet(&b).await, (data(2, b'b', 88), addr));
+    a.send_to(&ack(2), addr).await.unwrap();
+    b.send_to(&ack(2), addr).await.unwrap();
+    let mut completed = 0;
+    while completed < 2 {
+        match next_event(&mut events).await.kind {
+            EventKind::Completed(_) => completed += 1,
+            EventKind::Failed { reason, .. } => panic!("transfer failed: {reason}"),
+            _ => (),
+        }
+    }
+
+    // 会话结束后路由被移除，同一对端的新请求开启新会话。
+    // 路由在完成事件之后才注销，期间的请求会被丢弃，因此像客户端一样重发
+    assert_eq!(
+        request_until_reply(&a, &rrq("b.bin"), addr).await,
+        (data(1, b'b', 512), addr)
+    );
+    a.send_to(&ack(1), addr).await.unwrap();
+    assert_eq!(recv_packet(&a).await, (data(2, b'b', 88), addr));
+    a.send_to(&ack(2), addr).await.unwrap();
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 广播通道积压时丢失较早的事件，结束事件的订阅者仍能收到每个传输的结果
+#[tokio::test]
+async fn outcome_events_are_not_lost() {
+    let server_dir = test_dir("outcomes-server");
+    let client_dir = test_dir("outcomes-client");
+    std::fs::write(server_dir.path().join("small.bin"), b"small").unwrap();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+    let mut events = server.subscribe();
+    let mut outcomes = server.subscribe_outcomes();
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..Default::default()
+        },
+        512,
+        1,
+    );
+    // 每个传输至少有 RequestReceived、Negotiated 和 Completed 三个事件
+    for _ in 0..400 {
+        client
+            .get_file(addr, "small.bin".to_string())
+            .await
+            .unwrap();
+    }
+    client
+        .get_file(addr, "missing.bin".to_string())
+        .await
+        .unwrap_err();
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+
+    assert!(matches!(
+        events.recv().await,
+        Err(broadcast::error::RecvError::Lagged(_))
+    ));
+    // 服务端结束后通道关闭
+    let mut completed = 0;
+    let mut failed = 0;
+    while let Some(event) = outcomes.recv().await {
+        match event.kind {
+            EventKind::Completed(stats) => {
+                assert_eq!(stats.bytes, 5);
+                completed += 1;
+            }
+            EventKind::Failed { code, .. } => {
+                assert_eq!(code, 1);
+                failed += 1;
+            }
+            kind => panic!("unexpected {kind:?}"),
+        }
+    }
+    assert_eq!((completed, failed), (400, 1));
+}
+
+// 停机时等待进行中的传输完成
+#[tokio::test]
+async fn shutdown_drains_active_transfers() {
+    let server_dir = test_dir("drain-server");
+    let client_dir = test_dir("drain-client");
+    let image = vec![0x66; 200_000];
+    std::fs::write(server_dir.path().join("image.bin"), &image).unwrap();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        rate_limit: Some(500_000),
+        ..Default::default()
+    })
+    .with_drain_timeout(Duration::from_secs(10));
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..Default::default()
+        },
+        1468,
+        4,
+    );
+    let transfer = TransferHandle::new();
+    let mut progress = transfer.progress();
+    let stop = async {
+        progress.wait_for(|p| p.bytes > 0).await.unwrap();
+        shutdown.cancel();
+    };
+    let (res, ()) = tokio::join!(
+        client.get_file_with(addr, "image.bin".to_string(), &transfer),
+        stop
+    );
+    res.unwrap();
+    handle.await.unwrap().unwrap();
+    assert!(std::fs::read(client_dir.path().join("image.bin")).unwrap() == image);
+}
+
+// 超过停机期限时中止传输，并向对端发送 ERROR
+#[tokio::test]
+async fn shutdown_deadline_aborts_transfers() {
+    let server_dir = test_dir("abort-server");
+    let client_dir = test_dir("abort-client");
+    std::fs::write(server_dir.path().join("slow.bin"), vec![0x77; 500_000]).unwrap();
+    let server = common::server(SessionConfig {
+        directory: server_dir.path().to_path_buf(),
+        rate_limit: Some(100_000),
+        ..Default::default()
+    })
+    .with_drain_timeout(Duration::from_millis(200));
+    let mut events = server.subscribe();
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..Default::default()
+        },
+        512,
+        1,
+    );
+    let transfer = TransferHandle::new();
+    let mut progress = transfer.progress();
+    let stop = async {
+        progress.wait_for(|p| p.bytes > 0).await.unwrap();
+        shutdown.cancel();
+    };
+    let (res, ()) = tokio::join!(
+        client.get_file_with(addr, "slow.bin".to_string(), &transfer),
+        stop
+    );
+    let error = res.unwrap_err();
+    assert!(
+        error.to_string().contains("Server shutting down"),
+        "{error:#}"
+    );
+    handle.await.unwrap().unwrap();
+    loop {
+        match next_event(&mut events).await.kind {
+            EventKind::Failed { code, reason } => {
+                assert_eq!(code, 0);
+                assert!(reason.contains("Server shutting down"), "{reason}");
+                break;
+            }
+            EventKind::Completed(_) => panic!("transfer should be aborted"),
+            _ => (),
+        }
+    }
+}
+
+async fn next_failure(events: &mut broadcast::Receiver<TransferEvent>) -> (String, u16, String) {
+    let event = next_event(events).await;
+    assert!(
+        matches!(event.kind, EventKind::RequestReceived),
+        "{event:?}"
+    );
+    let event = next_event(events).await;
+    let EventKind::Failed { code, reason } = event.kind else {
+        panic!("expect Failed, got {event:?}");
+    };
+    (event.filename, code, reason)
+}
+
+// 请求的文件不存在或路径越界时回应 ERROR，并发出 Failed 事件
+#[tokio::test]
+async fn setup_failures_are_reported() {
+    let dir = test_dir("setup-failures");
+    let server = common::server(SessionConfig {
+        directory: dir.path().to_path_buf(),
+        ..Default::default()
+    });
+    let mut events = server.subscribe();
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    for (request, code) in [
+        (rrq("missing.bin"), 1),
+        (rrq("../secret.bin"), 2),
+        (wrq("/etc/passwd"), 2),
+    ] {
+        client.send_to(&request, addr).await.unwrap();
+        let (reply, _) = recv_packet(&client).await;
+        let TftpPacket::ERROR { code: error, msg } = reply else {
+            panic!("expect ERROR, got {reply:?}");
+        };
+        assert_eq!(error, code, "{msg}");
+        let (_, failed, reason) = next_failure(&mut events).await;
+        assert_eq!((failed, reason), (code, msg));
+    }
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 传输端口都被占用时借用监听端口回应 ERROR
+#[tokio::test]
+async fn session_bind_failure_is_reported() {
+    let dir = test_dir("bind-failure");
+    std::fs::write(dir.path().join("boot.bin"), b"boot").unwrap();
+    let taken = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let server = common::server(SessionConfig {
+        directory: dir.path().to_path_buf(),
+        port_range: Some(PortRange::new(port, port).unwrap()),
+        ..Default::default()
+    });
+    let mut events = server.subscribe();
+    let (addr, shutdown, handle) = common::start(server).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(&rrq("boot.bin"), addr).await.unwrap();
+    let (reply, from) = recv_packet(&client).await;
+    assert_eq!(from, addr);
+    let TftpPacket::ERROR { code: 0, msg } = reply else {
+        panic!("expect ERROR, got {reply:?}");
+    };
+    assert!(msg.starts_with("Session setup failed"), "{msg}");
+    let (filename, code, reason) = next_failure(&mut events).await;
+    assert_eq!((filename.as_str(), code, reason), ("boot.bin", 0, msg));
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}
+
+// 监听套接字的 recv_from 出错（如 ICMP 端口不可达）后继续处理请求
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn accept_loop_survives_recv_errors() {
+    use std::os::fd::AsRawFd;
+
+    let dir = test_dir("recv-error");
+    std::fs::write(dir.path().join("boot.bin"), vec![0x55; 2000]).unwrap();
+    let server = common::server(SessionConfig {
+        directory: dir.path().to_path_buf(),
+        timeout: 50,
+        retry: 3,
+        ..Default::default()
+    })
+    .with_single_port(true);
+    let mut events = server.subscribe();
+    let shutdown = server.shutdown_token();
+
+    // 未连接的 UDP 套接字默认忽略 ICMP 错误，开启 IP_RECVERR 后由 recv_from 返回
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let on: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_IP,
+            libc::IP_RECVERR,
+            &on as *const libc::c_int as *const libc::c_void,
+            size_of_val(&on) as libc::socklen_t,
+        )
+    };
+    assert_eq!(ret, 0);
+    let addr = socket.local_addr().unwrap();
+    let handle = tokio::spawn(async move { server.run_with_socket(socket).await });
+
+    // 对端收到第一块后关闭，单端口模式下服务端从监听端口重传，收到端口不可达
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    peer.send_to(&rrq("boot.bin"), addr).await.unwrap();
+    let (data, _) = recv_packet(&peer).await;
+    assert!(matches!(data, TftpPacket::DATA { block: 1, .. }));
+    drop(peer);
+    loop {
+        match next_event(&mut events).await.kind {
+            EventKind::Failed { .. } => break,
+            EventKind::Completed(_) => panic!("transfer should fail"),
+            _ => (),
+        }
+    }
+
+    let client_dir = test_dir("recv-error-client");
+    let client = TftpClient::new(
+        SessionConfig {
+            directory: client_dir.path().to_path_buf(),
+            ..Default::default()
+        },
+        512,
+        1,
+    );
+    client.get_file(addr, "boot.bin".to_string()).await.unwrap();
+
+    shutdown.cancel();
+    handle.await.unwrap().unwrap();
+}